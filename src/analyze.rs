@@ -12,7 +12,10 @@ pub fn analyze_dir<T, R>(dir_name: &Path, filter: &dyn PathFilter, compute_meta:
 
     let walk = WalkDir::new(dir_name).
         into_iter().
-        filter_entry(|e| filter.matches(e.path()));
+        filter_entry(|e| match e.metadata() {
+            Ok(metadata) => filter.matches_with_metadata(e.path(), &metadata),
+            Err(_) => filter.matches(e.path())
+        });
 
     for entry in walk {
         let entry = entry?;
@@ -33,6 +36,7 @@ pub fn analyze_dir<T, R>(dir_name: &Path, filter: &dyn PathFilter, compute_meta:
 
         entries.push(e)
     }
+    filter.check()?;
 
     entries.sort_unstable();
 
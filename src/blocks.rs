@@ -0,0 +1,366 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+
+use crate::entry::{BLOCK_SIZE, Entry, HashingReader, to_hex};
+
+pub const BLOCKS_DIR_NAME: &str = ".auditr-blocks";
+
+/// Hashes of the fixed-size blocks of a file. The block hashes are the leaves of a Merkle tree
+/// whose root is stored alongside to detect modifications of the block hashes themselves.
+#[derive(Debug, PartialEq)]
+pub struct BlockHashes {
+    pub block_size: u64,
+    pub leaves: Vec<String>,
+}
+
+impl BlockHashes {
+    /// Computes the block hashes of the given file.
+    pub fn compute(file_name: &Path, block_size: u64) -> Result<BlockHashes> {
+        BlockHashes::from_reader(BufReader::new(File::open(file_name)?), block_size)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R, block_size: u64) -> Result<BlockHashes> {
+        let mut leaves = Vec::new();
+        let mut buf = Vec::with_capacity(block_size as usize);
+
+        loop {
+            buf.clear();
+            let size = (&mut reader).take(block_size).read_to_end(&mut buf)?;
+            if size == 0 {
+                break;
+            }
+            leaves.push(to_hex(&Sha256::digest(&buf)));
+        }
+
+        Ok(BlockHashes {
+            block_size,
+            leaves,
+        })
+    }
+
+    pub fn load(file_name: &Path) -> Result<BlockHashes> {
+        let reader = BufReader::new(File::open(file_name)?);
+        let mut lines = reader.lines();
+
+        let block_size = lines.next().
+            ok_or_else(|| anyhow!("invalid block hashes: missing block size"))??.
+            parse::<u64>().
+            map_err(|err| anyhow!("invalid block hashes: invalid block size: {}", err))?;
+        let root = lines.next().
+            ok_or_else(|| anyhow!("invalid block hashes: missing root hash"))??;
+        let leaves = lines.collect::<Result<Vec<String>, _>>()?;
+
+        let hashes = BlockHashes {
+            block_size,
+            leaves,
+        };
+        if hashes.root() != root {
+            bail!("invalid block hashes: root hash does not match");
+        }
+
+        Ok(hashes)
+    }
+
+    pub fn save(&self, file_name: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        writeln!(writer, "{}", self.block_size)?;
+        writeln!(writer, "{}", self.root())?;
+        for leaf in self.leaves.iter() {
+            writeln!(writer, "{}", leaf)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Computes the root of the Merkle tree over all block hashes.
+    pub fn root(&self) -> String {
+        let mut level = self.leaves.iter().
+            map(|l| Sha256::digest(l.as_bytes()).to_vec()).
+            collect::<Vec<Vec<u8>>>();
+        if level.is_empty() {
+            return to_hex(&Sha256::digest(b""));
+        }
+
+        while level.len() > 1 {
+            level = level.chunks(2).
+                map(|pair| match pair {
+                    [left, right] => Sha256::new().chain(left).chain(right).result().to_vec(),
+                    [single] => single.clone(),
+                    _ => unreachable!()
+                }).
+                collect();
+        }
+
+        to_hex(&level[0])
+    }
+
+    /// Compares the block hashes with `actual` and returns the byte ranges (start inclusive, end
+    /// exclusive) of all blocks which differ. Adjacent blocks are merged into a single range.
+    pub fn diff(&self, actual: &BlockHashes, len: u64) -> Vec<(u64, u64)> {
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for (i, leaf) in self.leaves.iter().enumerate() {
+            if actual.leaves.get(i) == Some(leaf) {
+                continue;
+            }
+
+            let start = i as u64 * self.block_size;
+            let end = (start + self.block_size).min(len);
+            match ranges.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => ranges.push((start, end))
+            }
+        }
+        ranges
+    }
+}
+
+pub fn blocks_path(root: &Path, hash: &str) -> PathBuf {
+    root.join(BLOCKS_DIR_NAME).join(hash)
+}
+
+/// Synchronizes the stored block hashes with the given index entries. Block hashes of files which
+/// are no longer part of the index are removed. Block hashes are only recorded if `enabled` is set
+/// or block hashes were recorded for the directory before. Returns the entries whose files could
+/// not be read or do not match the indexed hash, together with the reason; no block hashes are
+/// recorded for them.
+pub fn update<'a>(root: &Path, entries: &'a [Entry], enabled: bool) -> Result<Vec<(&'a Entry, anyhow::Error)>> {
+    let dir = root.join(BLOCKS_DIR_NAME);
+    if !enabled && !dir.exists() {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(&dir)?;
+
+    let hashes = entries.iter().
+        map(|e| e.hash.as_str()).
+        collect::<HashSet<&str>>();
+    for file in fs::read_dir(&dir)? {
+        let file = file?;
+        if !hashes.contains(file.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(file.path())?;
+        }
+    }
+
+    let mut failed = Vec::new();
+    for e in entries.iter() {
+        let path = blocks_path(root, &e.hash);
+        if path.exists() {
+            continue;
+        }
+
+        let result = File::open(root.join(&e.path)).map_err(anyhow::Error::from).and_then(|file| {
+            let mut reader = HashingReader::new(BufReader::new(file));
+            let hashes = BlockHashes::from_reader(&mut reader, BLOCK_SIZE as u64)?;
+            if !reader.matches(&e.hash)? {
+                bail!("contents do not match the indexed hash");
+            }
+            Ok(hashes)
+        });
+        match result {
+            Ok(hashes) => hashes.save(&path)?,
+            Err(err) => failed.push((e, err)),
+        }
+    }
+
+    Ok(failed)
+}
+
+/// Returns the byte ranges of the file of the given index entry which do not match the recorded
+/// block hashes, or `None` if no block hashes were recorded for the entry.
+pub fn corrupted_ranges(root: &Path, expected: &Entry) -> Result<Option<Vec<(u64, u64)>>> {
+    let path = blocks_path(root, &expected.hash);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let recorded = BlockHashes::load(&path)?;
+    let actual = BlockHashes::compute(&root.join(&expected.path), recorded.block_size)?;
+    Ok(Some(recorded.diff(&actual, expected.len)))
+}
+
+/// Copies the given byte ranges from `source` into the file of the given index entry and truncates
+/// the file to the indexed length.
+pub fn copy_ranges(root: &Path, source: &Path, expected: &Entry, ranges: &[(u64, u64)]) -> Result<()> {
+    let mut reader = File::open(source)?;
+    let mut writer = File::options().write(true).open(root.join(&expected.path))?;
+
+    for &(start, end) in ranges {
+        reader.seek(SeekFrom::Start(start))?;
+        writer.seek(SeekFrom::Start(start))?;
+        let copied = std::io::copy(&mut (&mut reader).take(end - start), &mut writer)?;
+        if copied != end - start {
+            bail!("source file '{}' is too short", source.display());
+        }
+    }
+
+    writer.set_len(expected.len)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::entry::given_file_entry;
+
+    use super::*;
+
+    #[test]
+    fn test_compute() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        fs::write(root.path().join("a.bin"), b"0123456789")?;
+
+        // When
+        let hashes = BlockHashes::compute(&root.path().join("a.bin"), 4)?;
+
+        // Then
+        assert_eq!(hashes.block_size, 4);
+        assert_eq!(hashes.leaves, vec![
+            to_hex(&Sha256::digest(b"0123")),
+            to_hex(&Sha256::digest(b"4567")),
+            to_hex(&Sha256::digest(b"89")),
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_load() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let hashes = BlockHashes {
+            block_size: 4,
+            leaves: vec![String::from("a"), String::from("b"), String::from("c")],
+        };
+
+        // When
+        hashes.save(&root.path().join("blocks"))?;
+        let loaded = BlockHashes::load(&root.path().join("blocks"))?;
+
+        // Then
+        assert_eq!(loaded, hashes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_modified() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let hashes = BlockHashes {
+            block_size: 4,
+            leaves: vec![String::from("a"), String::from("b")],
+        };
+        hashes.save(&root.path().join("blocks"))?;
+
+        let contents = fs::read_to_string(root.path().join("blocks"))?;
+        fs::write(root.path().join("blocks"), contents.replace("\nb\n", "\nc\n"))?;
+
+        // When
+        let result = BlockHashes::load(&root.path().join("blocks"));
+
+        // Then
+        assert_eq!(result.map_err(|e| e.to_string()),
+                   Err(String::from("invalid block hashes: root hash does not match")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff() {
+        let recorded = BlockHashes {
+            block_size: 4,
+            leaves: ["a", "b", "c", "d", "e"].iter().map(|s| s.to_string()).collect(),
+        };
+        let actual = BlockHashes {
+            block_size: 4,
+            leaves: ["a", "x", "x", "d", "x"].iter().map(|s| s.to_string()).collect(),
+        };
+
+        assert_eq!(recorded.diff(&actual, 18), vec![(4, 12), (16, 18)]);
+    }
+
+    #[test]
+    fn test_corrupted_ranges_and_copy() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let mirror = tempdir()?;
+        let contents = vec![7; 3 * BLOCK_SIZE + 10];
+        let entry = given_file_entry(root.path(), "a.bin", &contents)?;
+        given_file_entry(mirror.path(), "a.bin", &contents)?;
+        update(root.path(), std::slice::from_ref(&entry), true)?;
+
+        let mut rotten = contents.clone();
+        rotten[BLOCK_SIZE + 5] = 0;
+        fs::write(root.path().join("a.bin"), &rotten)?;
+
+        // When
+        let ranges = corrupted_ranges(root.path(), &entry)?;
+        copy_ranges(root.path(), &mirror.path().join("a.bin"), &entry, ranges.as_deref().unwrap_or_default())?;
+
+        // Then
+        assert_eq!(ranges, Some(vec![(BLOCK_SIZE as u64, 2 * BLOCK_SIZE as u64)]));
+        assert_eq!(fs::read(root.path().join("a.bin"))?, contents);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_rotten() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let entry = given_file_entry(root.path(), "a.bin", b"contents")?;
+        fs::write(root.path().join("a.bin"), b"contentx")?;
+
+        // When
+        let failed = update(root.path(), std::slice::from_ref(&entry), true)?;
+
+        // Then
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, &entry);
+        assert_eq!(failed[0].1.to_string(), "contents do not match the indexed hash");
+        assert!(!blocks_path(root.path(), &entry.hash).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_missing_file() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let a = given_file_entry(root.path(), "a.bin", b"contents a")?;
+        let b = given_file_entry(root.path(), "b.bin", b"contents b")?;
+        fs::remove_file(root.path().join("a.bin"))?;
+
+        // When
+        let failed = update(root.path(), &[a.clone(), b.clone()], true)?;
+
+        // Then
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, &a);
+        assert!(blocks_path(root.path(), &b.hash).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_ranges_without_block_hashes() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let entry = given_file_entry(root.path(), "a.bin", b"contents")?;
+
+        // When
+        let ranges = corrupted_ranges(root.path(), &entry)?;
+
+        // Then
+        assert_eq!(ranges, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+
+use crate::entry::{BLOCK_SIZE, Entry, HashingReader, to_hex};
+
+pub const CHUNKS_DIR_NAME: &str = ".auditr-chunks";
+
+const MIN_CHUNK_SIZE: usize = 2 * 1024;
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+/// Chunk boundaries are found where the rolling hash has 13 zero bits, i.e. every 8 KiB on average.
+const BOUNDARY_MASK: u64 = (1 << 13) - 1;
+
+/// Random values mixed into the rolling hash for each byte value.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x6175_6469_7472_2d31;
+    let mut i = 0;
+    while i < table.len() {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// A content-defined chunk of a file, identified by a truncated hash of its contents.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub fingerprint: String,
+    pub len: u64,
+}
+
+/// Fingerprints of the content-defined chunks of a file. Since chunk boundaries depend on the
+/// contents only, inserting or removing bytes changes only the chunks around the modification.
+#[derive(Debug, PartialEq)]
+pub struct Fingerprints {
+    pub chunks: Vec<Chunk>,
+}
+
+impl Fingerprints {
+    /// Computes the chunk fingerprints of the given file.
+    pub fn compute(file_name: &Path) -> Result<Fingerprints> {
+        Fingerprints::from_reader(File::open(file_name)?)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Fingerprints> {
+        let mut chunks = Vec::new();
+        let mut chunk = Vec::with_capacity(MAX_CHUNK_SIZE);
+        let mut buf = vec![0; BLOCK_SIZE];
+        let mut hash: u64 = 0;
+
+        loop {
+            let size = reader.read(&mut buf)?;
+            if size == 0 {
+                break;
+            }
+
+            for &b in &buf[..size] {
+                chunk.push(b);
+                hash = (hash << 1).wrapping_add(GEAR[b as usize]);
+                if (chunk.len() >= MIN_CHUNK_SIZE && hash & BOUNDARY_MASK == 0) || chunk.len() >= MAX_CHUNK_SIZE {
+                    chunks.push(Fingerprints::chunk(&chunk));
+                    chunk.clear();
+                    hash = 0;
+                }
+            }
+        }
+
+        if !chunk.is_empty() {
+            chunks.push(Fingerprints::chunk(&chunk));
+        }
+
+        Ok(Fingerprints {
+            chunks,
+        })
+    }
+
+    fn chunk(data: &[u8]) -> Chunk {
+        Chunk {
+            fingerprint: to_hex(&Sha256::digest(data)[..8]),
+            len: data.len() as u64,
+        }
+    }
+
+    pub fn load(file_name: &Path) -> Result<Fingerprints> {
+        let reader = BufReader::new(File::open(file_name)?);
+        let mut chunks = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let (fingerprint, len) = line.split_once(' ').
+                ok_or_else(|| anyhow!("invalid chunk fingerprints: missing chunk length"))?;
+            let len = len.parse::<u64>().
+                map_err(|err| anyhow!("invalid chunk fingerprints: invalid chunk length: {}", err))?;
+            chunks.push(Chunk {
+                fingerprint: fingerprint.to_owned(),
+                len,
+            });
+        }
+
+        Ok(Fingerprints {
+            chunks,
+        })
+    }
+
+    pub fn save(&self, file_name: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        for chunk in self.chunks.iter() {
+            writeln!(writer, "{} {}", chunk.fingerprint, chunk.len)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns the total length of all chunks.
+    pub fn len(&self) -> u64 {
+        self.chunks.iter().map(|c| c.len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns the share of bytes (in percent) which are part of chunks found in both files.
+    pub fn similarity(&self, other: &Fingerprints) -> u32 {
+        let total = self.len() + other.len();
+        if total == 0 {
+            return 0;
+        }
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for c in self.chunks.iter() {
+            *counts.entry(&c.fingerprint).or_default() += 1;
+        }
+
+        let mut shared = 0;
+        for c in other.chunks.iter() {
+            if let Some(count) = counts.get_mut(c.fingerprint.as_str()).filter(|count| **count > 0) {
+                *count -= 1;
+                shared += 2 * c.len;
+            }
+        }
+
+        (shared * 100 / total) as u32
+    }
+}
+
+pub fn chunks_path(root: &Path, hash: &str) -> PathBuf {
+    root.join(CHUNKS_DIR_NAME).join(hash)
+}
+
+/// Synchronizes the stored chunk fingerprints with the given index entries. Fingerprints of files
+/// which are no longer part of the index are removed. Fingerprints are only recorded if `enabled`
+/// is set or fingerprints were recorded for the directory before. Returns the entries whose files
+/// could not be read or do not match the indexed hash, together with the reason; no fingerprints
+/// are recorded for them.
+pub fn update<'a>(root: &Path, entries: &'a [Entry], enabled: bool) -> Result<Vec<(&'a Entry, anyhow::Error)>> {
+    let dir = root.join(CHUNKS_DIR_NAME);
+    if !enabled && !dir.exists() {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(&dir)?;
+
+    let hashes = entries.iter().
+        map(|e| e.hash.as_str()).
+        collect::<HashSet<&str>>();
+    for file in fs::read_dir(&dir)? {
+        let file = file?;
+        if !hashes.contains(file.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(file.path())?;
+        }
+    }
+
+    let mut failed = Vec::new();
+    for e in entries.iter() {
+        let path = chunks_path(root, &e.hash);
+        if path.exists() {
+            continue;
+        }
+
+        let result = File::open(root.join(&e.path)).map_err(anyhow::Error::from).and_then(|file| {
+            let mut reader = HashingReader::new(file);
+            let fingerprints = Fingerprints::from_reader(&mut reader)?;
+            if !reader.matches(&e.hash)? {
+                bail!("contents do not match the indexed hash");
+            }
+            Ok(fingerprints)
+        });
+        match result {
+            Ok(fingerprints) => fingerprints.save(&path)?,
+            Err(err) => failed.push((e, err)),
+        }
+    }
+
+    Ok(failed)
+}
+
+/// Pairs removed files of the index with added files whose contents are at least `threshold`
+/// percent similar. Removed files without recorded fingerprints are skipped. Pairs with the
+/// highest similarity are chosen first; returns the pairs together with their similarity.
+pub fn similar_pairs<'a>(root: &Path, removed: &[&'a Entry], added: &[&'a Entry], threshold: u32) -> Result<Vec<(&'a Entry, &'a Entry, u32)>> {
+    let mut old = Vec::new();
+    for &r in removed.iter().filter(|r| r.len > 0) {
+        let path = chunks_path(root, &r.hash);
+        if path.exists() {
+            old.push((r, Fingerprints::load(&path)?));
+        }
+    }
+    if old.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The similarity cannot exceed the ratio of the smaller to the average file size.
+    let may_match = |a: &Entry, b: &Entry| 200 * a.len.min(b.len) >= threshold as u64 * (a.len + b.len);
+
+    let mut candidates = Vec::new();
+    for &a in added.iter().filter(|a| a.len > 0) {
+        if !old.iter().any(|(r, _)| may_match(r, a)) {
+            continue;
+        }
+
+        let new = Fingerprints::compute(&root.join(&a.path))?;
+        for (r, fingerprints) in old.iter().filter(|(r, _)| may_match(r, a)) {
+            let similarity = fingerprints.similarity(&new);
+            if similarity >= threshold {
+                candidates.push((*r, a, similarity));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.2.cmp(&a.2).
+        then_with(|| a.0.path.cmp(&b.0.path)).
+        then_with(|| a.1.path.cmp(&b.1.path)));
+
+    let mut paired = HashSet::new();
+    let mut pairs = Vec::new();
+    for (r, a, similarity) in candidates {
+        if !paired.contains(&r.path) && !paired.contains(&a.path) {
+            paired.insert(&r.path);
+            paired.insert(&a.path);
+            pairs.push((r, a, similarity));
+        }
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::entry::given_file_entry;
+
+    use super::*;
+
+    fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect()
+    }
+
+    #[test]
+    fn test_from_reader_chunk_sizes() -> Result<()> {
+        let data = random_bytes(1, 512 * 1024);
+
+        let fingerprints = Fingerprints::from_reader(&data[..])?;
+
+        assert_eq!(fingerprints.len(), data.len() as u64);
+        assert!(fingerprints.chunks.len() > 1);
+        for c in fingerprints.chunks[..fingerprints.chunks.len() - 1].iter() {
+            assert!(c.len >= MIN_CHUNK_SIZE as u64 && c.len <= MAX_CHUNK_SIZE as u64);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_similarity_insertion() -> Result<()> {
+        let data = random_bytes(2, 256 * 1024);
+        let mut modified = data.clone();
+        modified.splice(100_000..100_000, b"inserted".iter().copied());
+
+        let original = Fingerprints::from_reader(&data[..])?;
+        let modified = Fingerprints::from_reader(&modified[..])?;
+
+        assert_eq!(original.similarity(&original), 100);
+        assert!(original.similarity(&modified) >= 80);
+        assert_eq!(original.similarity(&Fingerprints::from_reader(&random_bytes(3, 256 * 1024)[..])?), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_load() -> Result<()> {
+        let root = tempdir()?;
+        let fingerprints = Fingerprints::from_reader(&random_bytes(4, 64 * 1024)[..])?;
+
+        fingerprints.save(&root.path().join("chunks"))?;
+        let loaded = Fingerprints::load(&root.path().join("chunks"))?;
+
+        assert_eq!(loaded, fingerprints);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_rotten() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let entry = given_file_entry(root.path(), "a.bin", random_bytes(5, 16 * 1024))?;
+        fs::write(root.path().join("a.bin"), random_bytes(6, 16 * 1024))?;
+
+        // When
+        let failed = update(root.path(), std::slice::from_ref(&entry), true)?;
+
+        // Then
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, &entry);
+        assert_eq!(failed[0].1.to_string(), "contents do not match the indexed hash");
+        assert!(!chunks_path(root.path(), &entry.hash).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_missing_file() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let a = given_file_entry(root.path(), "a.bin", random_bytes(5, 16 * 1024))?;
+        let b = given_file_entry(root.path(), "b.bin", random_bytes(6, 16 * 1024))?;
+        fs::remove_file(root.path().join("a.bin"))?;
+
+        // When
+        let failed = update(root.path(), &[a.clone(), b.clone()], true)?;
+
+        // Then
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, &a);
+        assert!(chunks_path(root.path(), &b.hash).exists());
+
+        Ok(())
+    }
+}
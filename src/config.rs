@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::entry::Normalization;
+use crate::format::Format;
+use crate::summary::SortOrder;
+
+pub const CONFIG_FILENAME: &str = ".auditr.toml";
+
+/// Default values of options set in configuration files. Values of the user configuration are
+/// overridden by the configuration of the root directory, which is overridden by command line
+/// arguments. Unset values use the default of the respective command line argument.
+///
+/// The key can only be set in the user configuration. The configuration of the root directory is
+/// part of the audited tree, but not covered by the signature of the index.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub normalization: Option<String>,
+    pub key: Option<String>,
+    pub parity: Option<u32>,
+    pub blocks: Option<bool>,
+    pub chunks: Option<bool>,
+    pub similarity: Option<u32>,
+    pub format: Option<String>,
+    pub verbose: Option<bool>,
+    pub summary: Option<u64>,
+    pub sort: Option<String>,
+    pub respect_gitignore: Option<bool>,
+    pub exclude: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Loads the configuration file with the given name, or returns `None` if it does not exist.
+    /// A relative key path is resolved against the directory of the configuration file.
+    pub fn load(file_name: &Path) -> Result<Option<Config>> {
+        Config::load_layer(file_name, false)
+    }
+
+    /// Loads the configuration file of the given root directory, or returns `None` if it does not
+    /// exist. The file must not set a key.
+    pub fn load_root(root: &Path) -> Result<Option<Config>> {
+        Config::load_layer(&root.join(CONFIG_FILENAME), true)
+    }
+
+    fn load_layer(file_name: &Path, root: bool) -> Result<Option<Config>> {
+        if !file_name.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(file_name)?;
+        let mut config: Config = toml::from_str(&contents).
+            with_context(|| format!("Invalid configuration file '{}'", file_name.display()))?;
+        config.validate(root).
+            with_context(|| format!("Invalid configuration file '{}'", file_name.display()))?;
+
+        if let (Some(key), Some(dir)) = (&config.key, file_name.parent()) {
+            config.key = Some(dir.join(key).to_string_lossy().into_owned());
+        }
+        Ok(Some(config))
+    }
+
+    /// Returns the built-in defaults of all options which have one.
+    pub fn defaults() -> Config {
+        Config {
+            normalization: Some(String::from("nfc")),
+            blocks: Some(false),
+            chunks: Some(false),
+            format: Some(String::from("text")),
+            verbose: Some(false),
+            sort: Some(String::from("path")),
+            respect_gitignore: Some(false),
+            exclude: Some(Vec::new()),
+            include: Some(Vec::new()),
+            ..Config::default()
+        }
+    }
+
+    fn validate(&self, root: bool) -> Result<()> {
+        if root && self.key.is_some() {
+            bail!("key can only be set in the user configuration or with --key");
+        }
+        if let Some(n) = &self.normalization {
+            n.parse::<Normalization>()?;
+        }
+        if let Some(f) = &self.format {
+            f.parse::<Format>()?;
+        }
+        if let Some(s) = &self.sort {
+            s.parse::<SortOrder>()?;
+        }
+        for (name, value) in [("parity", self.parity), ("similarity", self.similarity)].iter() {
+            if let Some(v) = value {
+                if !(1..=100).contains(v) {
+                    bail!("invalid {} '{}', expected a percentage from 1 to 100", name, v);
+                }
+            }
+        }
+        if self.summary == Some(0) {
+            bail!("invalid summary depth '0', expected at least 1");
+        }
+        Ok(())
+    }
+
+    /// Returns a configuration with all values of `other` and the remaining values of this one.
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            normalization: other.normalization.or(self.normalization),
+            key: other.key.or(self.key),
+            parity: other.parity.or(self.parity),
+            blocks: other.blocks.or(self.blocks),
+            chunks: other.chunks.or(self.chunks),
+            similarity: other.similarity.or(self.similarity),
+            format: other.format.or(self.format),
+            verbose: other.verbose.or(self.verbose),
+            summary: other.summary.or(self.summary),
+            sort: other.sort.or(self.sort),
+            respect_gitignore: other.respect_gitignore.or(self.respect_gitignore),
+            exclude: other.exclude.or(self.exclude),
+            include: other.include.or(self.include),
+        }
+    }
+
+    /// Loads the configuration files applying to the given root directory, starting with the one
+    /// with the lowest precedence. Files which do not exist are returned with `None`.
+    pub fn layers(root: &Path) -> Result<Vec<(PathBuf, Option<Config>)>> {
+        let mut layers = Vec::new();
+        if let Some(file) = user_config_path() {
+            let config = Config::load(&file)?;
+            layers.push((file, config));
+        }
+        layers.push((root.join(CONFIG_FILENAME), Config::load_root(root)?));
+        Ok(layers)
+    }
+
+    /// Loads and merges all configuration files applying to the given root directory.
+    pub fn load_for(root: &Path) -> Result<Config> {
+        let config = Config::layers(root)?.into_iter().
+            filter_map(|(_, c)| c).
+            fold(Config::default(), Config::merge);
+        Ok(config)
+    }
+
+    /// Returns the normalization form given on the command line, or the configured one.
+    pub fn normalization(&self, arg: Option<&str>) -> Result<Normalization> {
+        arg.or(self.normalization.as_deref()).map(str::parse).unwrap_or_else(|| Ok(Normalization::default()))
+    }
+
+    /// Returns the key file given on the command line, or the configured one.
+    pub fn key<'a>(&'a self, arg: Option<&'a str>) -> Option<&'a str> {
+        arg.or(self.key.as_deref())
+    }
+
+    pub fn format(&self, arg: Option<&str>) -> Result<Format> {
+        arg.or(self.format.as_deref()).map(str::parse).unwrap_or_else(|| Ok(Format::default()))
+    }
+
+    pub fn sort(&self, arg: Option<&str>) -> Result<SortOrder> {
+        arg.or(self.sort.as_deref()).map(str::parse).unwrap_or_else(|| Ok(SortOrder::default()))
+    }
+}
+
+/// Returns the path of the configuration file of the user, which is located in `$XDG_CONFIG_HOME`
+/// or `~/.config`.
+pub fn user_config_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME").
+        filter(|d| !d.is_empty()).
+        map(PathBuf::from).
+        or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))?;
+    Some(dir.join("auditr").join("config.toml"))
+}
+
+/// Describes the effective configuration of the given root directory, i.e. the value of every
+/// option together with the configuration file it was taken from.
+pub fn describe(root: &Path) -> Result<String> {
+    let mut values: BTreeMap<String, (toml::Value, String)> = BTreeMap::new();
+    let mut out = String::new();
+
+    let mut layers = vec![(String::from("default"), Config::defaults())];
+    writeln!(out, "# Configuration files:")?;
+    for (file, config) in Config::layers(root)? {
+        match config {
+            Some(config) => {
+                writeln!(out, "#   {}", file.display())?;
+                layers.push((file.display().to_string(), config));
+            }
+            None => writeln!(out, "#   {} (not found)", file.display())?
+        }
+    }
+    writeln!(out)?;
+
+    for (source, config) in layers {
+        for (key, value) in toml::Table::try_from(&config)? {
+            values.insert(key, (value, source.clone()));
+        }
+    }
+
+    let keys = toml::Table::try_from(Config {
+        key: Some(String::new()),
+        parity: Some(0),
+        similarity: Some(0),
+        summary: Some(0),
+        ..Config::defaults()
+    })?;
+    for key in keys.keys() {
+        match values.get(key) {
+            Some((value, source)) => writeln!(out, "{} = {}  # {}", key, value, source)?,
+            None => writeln!(out, "# {} is not set", key)?
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_load() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        let path = temp.path().join(CONFIG_FILENAME);
+        fs::write(&path, indoc!(r#"
+            normalization = "nfd"
+            parity = 20
+            respect-gitignore = true
+            exclude = ["*.tmp"]
+        "#))?;
+
+        // When
+        let config = Config::load(&path)?;
+
+        // Then
+        assert_eq!(config, Some(Config {
+            normalization: Some(String::from("nfd")),
+            parity: Some(20),
+            respect_gitignore: Some(true),
+            exclude: Some(vec![String::from("*.tmp")]),
+            ..Config::default()
+        }));
+        assert_eq!(Config::load(&temp.path().join("missing.toml"))?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_invalid() -> Result<()> {
+        let temp = tempdir()?;
+        let path = temp.path().join(CONFIG_FILENAME);
+
+        fs::write(&path, "jobs = 4\n")?;
+        assert!(Config::load(&path).is_err());
+
+        fs::write(&path, "parity = 200\n")?;
+        let err = Config::load(&path).unwrap_err();
+        assert_eq!(format!("{:#}", err), format!("Invalid configuration file '{}': invalid parity '200', expected a percentage from 1 to 100", path.display()));
+
+        fs::write(&path, "format = \"xml\"\n")?;
+        assert!(Config::load(&path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_key() -> Result<()> {
+        let temp = tempdir()?;
+        let path = temp.path().join("config.toml");
+        fs::write(&path, "key = \"keys/auditr\"\n")?;
+
+        let config = Config::load(&path)?.unwrap();
+
+        assert_eq!(config.key(None), Some(temp.path().join("keys/auditr").to_str().unwrap()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let temp = tempdir()?;
+        let user = Config {
+            normalization: Some(String::from("nfd")),
+            key: Some(String::from("/keys/auditr")),
+            parity: Some(10),
+            ..Config::default()
+        };
+        let root = Config {
+            parity: Some(20),
+            blocks: Some(true),
+            ..Config::default()
+        };
+
+        let config = user.merge(root);
+
+        assert_eq!(config, Config {
+            normalization: Some(String::from("nfd")),
+            key: Some(String::from("/keys/auditr")),
+            parity: Some(20),
+            blocks: Some(true),
+            ..Config::default()
+        });
+        assert_eq!(config.key(None), Some("/keys/auditr"));
+        assert_eq!(config.key(Some("other")), Some("other"));
+        assert_eq!(config.normalization(None).unwrap(), Normalization::Nfd);
+        assert_eq!(config.normalization(Some("none")).unwrap(), Normalization::None);
+        assert_eq!(config.format(None).unwrap(), Format::Text);
+
+        // The root layer is part of the audited tree and must not override the key
+        fs::write(temp.path().join(CONFIG_FILENAME), "key = \"/tmp/evil\"\n")?;
+        let err = Config::load_root(temp.path()).unwrap_err();
+        assert_eq!(format!("{:#}", err), format!("Invalid configuration file '{}': key can only be set in the user configuration or with --key",
+                                                 temp.path().join(CONFIG_FILENAME).display()));
+
+        Ok(())
+    }
+}
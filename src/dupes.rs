@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::entry::Entry;
+
+/// Files with identical contents.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DuplicateSet {
+    pub hash: String,
+    pub size: u64,
+    /// Bytes which could be saved by keeping a single copy.
+    pub wasted: u64,
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub sets: Vec<DuplicateSet>,
+    pub files: u64,
+    pub wasted: u64,
+    /// Duplicate candidates which no longer exist.
+    pub missing: Vec<String>,
+}
+
+/// Groups the given entries by their hash. Only entries of the same size are compared, and empty
+/// files are ignored. Returns all groups containing more than one entry.
+pub fn find(entries: &[Entry]) -> Vec<Vec<&Entry>> {
+    let mut by_size: HashMap<u64, Vec<&Entry>> = HashMap::new();
+    for e in entries.iter().filter(|e| e.len > 0) {
+        by_size.entry(e.len).or_default().push(e);
+    }
+
+    let mut groups = Vec::new();
+    for candidates in by_size.into_values().filter(|c| c.len() > 1) {
+        let mut by_hash: HashMap<&str, Vec<&Entry>> = HashMap::new();
+        for e in candidates {
+            by_hash.entry(&e.hash).or_default().push(e);
+        }
+        groups.extend(by_hash.into_values().filter(|g| g.len() > 1));
+    }
+
+    for group in groups.iter_mut() {
+        group.sort_unstable();
+    }
+    groups
+}
+
+/// Computes the hashes and metadata of all files in the given groups again, e.g. to rule out files
+/// modified since the index was updated. Returns the rehashed entries and the entries of files
+/// which no longer exist.
+pub fn rehash<'a, T, R>(root: &Path, groups: &[Vec<&'a Entry>], mut update: T) -> Result<(Vec<Entry>, Vec<&'a Entry>)> where
+    T: FnMut(u64) -> R {
+    let (existing, missing): (Vec<&Entry>, Vec<&Entry>) = groups.iter().
+        flatten().
+        partition(|e| root.join(&e.path).is_file());
+
+    let rehashed = existing.into_iter().
+        map(|e| {
+            let mut e = e.clone();
+            e.update_meta(root).
+                with_context(|| format!("Failed to read metadata of file '{}'", e))?;
+            e.update_hash(root, true, &mut update).
+                with_context(|| format!("Failed to hash file '{}'", e))?;
+            Ok(e)
+        }).
+        collect::<Result<Vec<Entry>>>()?;
+    Ok((rehashed, missing))
+}
+
+/// Creates the report of the given duplicate groups, sorted by the wasted bytes.
+pub fn report(groups: &[Vec<&Entry>], missing: &[&Entry]) -> Report {
+    let mut sets = groups.iter().
+        map(|g| DuplicateSet {
+            hash: g[0].hash.clone(),
+            size: g[0].len,
+            wasted: g[0].len * (g.len() as u64 - 1),
+            paths: g.iter().map(|e| e.to_string()).collect(),
+        }).
+        collect::<Vec<DuplicateSet>>();
+    sets.sort_by(|a, b| b.wasted.cmp(&a.wasted).then_with(|| a.paths.cmp(&b.paths)));
+
+    Report {
+        files: sets.iter().map(|s| s.paths.len() as u64).sum(),
+        wasted: sets.iter().map(|s| s.wasted).sum(),
+        sets,
+        missing: missing.iter().map(|e| e.to_string()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::entry::given_entry;
+
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let entries = vec![
+            given_entry("a.txt", "h1", 10, 123),
+            given_entry("b.txt", "h2", 10, 123),
+            given_entry("c.txt", "h1", 10, 123),
+            given_entry("d.txt", "h3", 100, 123),
+            given_entry("e.txt", "h3", 100, 123),
+            given_entry("f.txt", "h1", 10, 123),
+            given_entry("empty1.txt", "h0", 0, 123),
+            given_entry("empty2.txt", "h0", 0, 123),
+        ];
+
+        let report = report(&find(&entries), &[]);
+
+        assert_eq!(report, Report {
+            sets: vec![
+                DuplicateSet {
+                    hash: String::from("h3"),
+                    size: 100,
+                    wasted: 100,
+                    paths: vec![String::from("d.txt"), String::from("e.txt")],
+                },
+                DuplicateSet {
+                    hash: String::from("h1"),
+                    size: 10,
+                    wasted: 20,
+                    paths: vec![String::from("a.txt"), String::from("c.txt"), String::from("f.txt")],
+                },
+            ],
+            files: 5,
+            wasted: 120,
+            missing: vec![],
+        });
+    }
+
+    #[test]
+    fn test_rehash_missing() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        std::fs::write(temp.path().join("a.txt"), "abc")?;
+        let entries = vec![
+            given_entry("a.txt", "h1", 1, 123),
+            given_entry("b.txt", "h1", 1, 123),
+        ];
+
+        let (rehashed, missing) = rehash(temp.path(), &find(&entries), |_| ())?;
+
+        assert_eq!(rehashed.len(), 1);
+        assert_eq!(rehashed[0].path, PathBuf::from("a.txt"));
+        assert_ne!(rehashed[0].hash, "h1");
+        assert_eq!(rehashed[0].len, 3);
+        assert_ne!(rehashed[0].modified, 123);
+        assert_eq!(missing, vec![&entries[1]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_same_hash_different_size() {
+        let entries = vec![
+            given_entry("a.txt", "h1", 10, 123),
+            given_entry("b.txt", "h1", 20, 123),
+        ];
+
+        assert!(find(&entries).is_empty());
+    }
+}
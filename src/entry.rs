@@ -2,14 +2,52 @@ use std::{fmt, fs};
 use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
+use std::io;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::UNIX_EPOCH;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use sha2::{Digest, Sha256};
-use sha2::digest::generic_array::functional::FunctionalSequence;
-use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+
+pub const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Unicode normalization form used to compare and display paths. Paths which only differ in
+/// their normalization form are considered the same file name unless `None` is used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    #[default]
+    Nfc,
+    Nfd,
+    /// Paths are compared byte by byte.
+    None,
+}
+
+impl Normalization {
+    pub fn normalize(self, path: &Path) -> String {
+        let path = path.to_string_lossy();
+        match self {
+            Normalization::Nfc => path.nfc().collect(),
+            Normalization::Nfd => path.nfd().collect(),
+            Normalization::None => path.into_owned()
+        }
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            "none" => Ok(Normalization::None),
+            _ => bail!("invalid normalization form '{}', expected nfc, nfd or none", s)
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Entry {
@@ -26,9 +64,12 @@ impl Display for Entry {
     }
 }
 
+/// Entries are ordered by their normalized path. Paths which only differ in their normalization
+/// form are ordered by their raw path, so that both files are kept apart.
 impl Ord for Entry {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.norm_path.cmp(&other.norm_path)
+        self.norm_path.cmp(&other.norm_path).
+            then_with(|| self.path.cmp(&other.path))
     }
 }
 
@@ -40,7 +81,7 @@ impl PartialOrd for Entry {
 
 impl PartialEq for Entry {
     fn eq(&self, other: &Self) -> bool {
-        self.norm_path == other.norm_path
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -50,7 +91,7 @@ impl Entry {
     pub fn from_path(path: &Path) -> Entry {
         Entry {
             path: path.to_path_buf(),
-            norm_path: path.to_str().map(|s| s.nfc().to_string()).unwrap_or("-".to_owned()),
+            norm_path: Normalization::default().normalize(path),
             hash: String::new(),
             len: 0,
             modified: 0,
@@ -92,7 +133,7 @@ impl Entry {
         T: FnMut(u64) -> R {
         let mut hasher = Sha256::new();
         let mut file = File::open(file_name)?;
-        let mut buf = [0; 1024 * 1024];
+        let mut buf = [0; BLOCK_SIZE];
 
         loop {
             let size = file.read(&mut buf)?;
@@ -105,8 +146,93 @@ impl Entry {
             hasher.input(&buf[..]);
         }
 
-        Ok(hasher.result().
-            map(|b| format!("{:02x}", b)).
-            to_vec().join(""))
+        Ok(to_hex(&hasher.result()))
+    }
+}
+
+/// Returns an index entry for tests without a file on disk.
+#[cfg(test)]
+pub(crate) fn given_entry(name: &str, hash: &str, len: u64, modified: u64) -> Entry {
+    Entry {
+        path: PathBuf::from(name),
+        norm_path: name.to_owned(),
+        hash: hash.to_owned(),
+        len,
+        modified,
+    }
+}
+
+/// Writes a file with the given contents for tests and returns its entry with metadata and hash.
+#[cfg(test)]
+pub(crate) fn given_file_entry<C: AsRef<[u8]>>(root: &Path, name: &str, contents: C) -> Result<Entry> {
+    fs::write(root.join(name), contents)?;
+    let mut e = Entry::from_path(Path::new(name));
+    e.update_meta(root)?;
+    e.update_hash(root, true, &mut |_| ())?;
+    Ok(e)
+}
+
+/// Reader computing the SHA-256 hash of all data read through it. Data derived from a file (like
+/// parity data) is checked against the indexed hash in the same pass, so that the derived data
+/// never reflects contents which have already rotted.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Reads the remaining data and returns whether the hash of all data matches the given hash.
+    pub fn matches(mut self, hash: &str) -> Result<bool> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(to_hex(&self.hasher.result()) == hash)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.hasher.input(&buf[..size]);
+        Ok(size)
+    }
+}
+
+/// Returns the normalization form of the given path, e.g. to describe collisions. Paths in both
+/// forms (like plain ASCII paths) are reported as NFC.
+pub fn normalization_form(path: &Path) -> &'static str {
+    let path = path.to_string_lossy();
+    match (is_nfc(&path), is_nfd(&path)) {
+        (true, _) => "NFC",
+        (false, true) => "NFD",
+        (false, false) => "mixed"
+    }
+}
+
+/// Normalizes the paths of the given entries with the given form and sorts them accordingly.
+pub fn normalize(entries: &mut [Entry], form: Normalization) {
+    for e in entries.iter_mut() {
+        e.norm_path = form.normalize(&e.path);
     }
+    entries.sort_unstable();
+}
+
+/// Returns all pairs of entries whose paths are equal after normalization. The entries must be
+/// sorted.
+pub fn collisions(entries: &[Entry]) -> Vec<(&Entry, &Entry)> {
+    entries.windows(2).
+        filter(|w| w[0].norm_path == w[1].norm_path).
+        map(|w| (&w[0], &w[1])).
+        collect()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().
+        map(|b| format!("{:02x}", b)).
+        collect()
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+use std::fs::Metadata;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::filter::{explain_all, PathFilter, RuleMatch};
+
+/// Includes a path only if all filters include it.
+pub struct And<'a> {
+    filters: Vec<Box<dyn PathFilter + 'a>>,
+}
+
+impl<'a> And<'a> {
+    pub fn new(filters: Vec<Box<dyn PathFilter + 'a>>) -> And<'a> {
+        And {
+            filters,
+        }
+    }
+}
+
+impl PathFilter for And<'_> {
+    fn matches(&self, p: &Path) -> bool {
+        self.filters.iter().all(|f| f.matches(p))
+    }
+
+    fn matches_with_metadata(&self, p: &Path, metadata: &Metadata) -> bool {
+        self.filters.iter().all(|f| f.matches_with_metadata(p, metadata))
+    }
+
+    fn matches_path(&self, p: &Path) -> bool {
+        self.filters.iter().all(|f| f.matches_path(p))
+    }
+
+    fn explain(&self, p: &Path) -> Option<RuleMatch> {
+        explain_all(self.filters.iter().map(|f| f.as_ref()), p)
+    }
+
+    fn check(&self) -> Result<()> {
+        self.filters.iter().try_for_each(|f| f.check())
+    }
+}
+
+/// Includes a path if any of the filters includes it.
+pub struct Or<'a> {
+    filters: Vec<Box<dyn PathFilter + 'a>>,
+}
+
+impl<'a> Or<'a> {
+    pub fn new(filters: Vec<Box<dyn PathFilter + 'a>>) -> Or<'a> {
+        Or {
+            filters,
+        }
+    }
+}
+
+impl PathFilter for Or<'_> {
+    fn matches(&self, p: &Path) -> bool {
+        self.filters.iter().any(|f| f.matches(p))
+    }
+
+    fn matches_with_metadata(&self, p: &Path, metadata: &Metadata) -> bool {
+        self.filters.iter().any(|f| f.matches_with_metadata(p, metadata))
+    }
+
+    fn matches_path(&self, p: &Path) -> bool {
+        self.filters.iter().any(|f| f.matches_path(p))
+    }
+
+    fn explain(&self, p: &Path) -> Option<RuleMatch> {
+        match self.filters.iter().find(|f| f.matches(p)) {
+            Some(filter) => filter.explain(p),
+            None => self.filters.iter().find_map(|f| f.explain(p))
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        self.filters.iter().try_for_each(|f| f.check())
+    }
+}
+
+/// Includes a path if the filter excludes it.
+pub struct Not<'a> {
+    filter: Box<dyn PathFilter + 'a>,
+}
+
+impl<'a> Not<'a> {
+    pub fn new(filter: Box<dyn PathFilter + 'a>) -> Not<'a> {
+        Not {
+            filter,
+        }
+    }
+}
+
+impl PathFilter for Not<'_> {
+    fn matches(&self, p: &Path) -> bool {
+        !self.filter.matches(p)
+    }
+
+    fn matches_with_metadata(&self, p: &Path, metadata: &Metadata) -> bool {
+        !self.filter.matches_with_metadata(p, metadata)
+    }
+
+    fn matches_path(&self, p: &Path) -> bool {
+        !self.filter.matches_path(p)
+    }
+
+    fn explain(&self, p: &Path) -> Option<RuleMatch> {
+        self.filter.explain(p).map(|m| RuleMatch {
+            include: !m.include,
+            ..m
+        })
+    }
+
+    fn check(&self) -> Result<()> {
+        self.filter.check()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use crate::filter::globfilter::GlobPathFilter;
+
+    use super::*;
+
+    #[test]
+    fn test_and() -> Result<()> {
+        let root = Path::new("/some/path");
+        let filter = And::new(vec![
+            Box::new(GlobPathFilter::new(root, vec!["*.txt".try_into()?], true)?),
+            Box::new(GlobPathFilter::new(root, vec!["a/*".try_into()?], true)?),
+        ]);
+
+        assert!(!filter.matches(Path::new("/some/path/b.txt")));
+        assert!(!filter.matches(Path::new("/some/path/a/b.log")));
+        assert!(filter.matches(Path::new("/some/path/b.log")));
+        assert!(And::new(vec![]).matches(Path::new("/some/path/b.log")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_or() -> Result<()> {
+        let root = Path::new("/some/path");
+        let filter = Or::new(vec![
+            Box::new(GlobPathFilter::new(root, vec!["*.txt".try_into()?], true)?),
+            Box::new(GlobPathFilter::new(root, vec!["a/*".try_into()?], true)?),
+        ]);
+
+        assert!(filter.matches(Path::new("/some/path/b.txt")));
+        assert!(filter.matches(Path::new("/some/path/a/b.log")));
+        assert!(!filter.matches(Path::new("/some/path/a/b.txt")));
+        assert!(!Or::new(vec![]).matches(Path::new("/some/path/b.log")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_not() -> Result<()> {
+        let root = Path::new("/some/path");
+        let filter = Not::new(Box::new(GlobPathFilter::new(root, vec!["*.txt".try_into()?], true)?));
+
+        assert!(filter.matches(Path::new("/some/path/b.txt")));
+        assert!(!filter.matches(Path::new("/some/path/b.log")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let root = Path::new("/some/path");
+        let filter = And::new(vec![
+            Box::new(Not::new(Box::new(GlobPathFilter::new(root, vec!["!*.txt".try_into()?], false)?))),
+            Box::new(GlobPathFilter::new(root, vec!["a/*".try_into()?], true)?),
+        ]);
+
+        let rule = filter.explain(Path::new("/some/path/a/b.log")).unwrap();
+        assert_eq!((rule.rule.as_str(), rule.include), ("a/*", false));
+        let rule = filter.explain(Path::new("/some/path/b.txt")).unwrap();
+        assert_eq!((rule.rule.as_str(), rule.include), ("!*.txt", false));
+        assert!(filter.explain(Path::new("/some/path/b.log")).is_none());
+
+        Ok(())
+    }
+}
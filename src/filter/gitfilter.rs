@@ -0,0 +1,352 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+
+use crate::filter::{normalize, read_rules, Origin, PathFilter, RuleMatch};
+
+pub const GIT_FILTER_FILENAME: &str = ".auditrignore-git";
+
+/// First line of an ignore file which selects gitignore semantics instead of glob rules.
+pub const GIT_FILTER_HEADER: &str = "# auditr: gitignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A rule following the semantics of `.gitignore` files: patterns without a slash (apart from a
+/// trailing one) match at any directory level, other patterns are anchored at the root. A trailing
+/// slash restricts the rule to directories and a leading `!` re-includes matching paths.
+#[derive(Clone, Debug)]
+pub struct GitRule {
+    pattern: Pattern,
+    include: bool,
+    dir_only: bool,
+    case_sensitive: bool,
+    text: String,
+    origin: Origin,
+}
+
+impl GitRule {
+    pub(crate) fn to_match(&self) -> RuleMatch {
+        RuleMatch {
+            origin: self.origin.clone(),
+            rule: self.text.clone(),
+            include: self.include,
+        }
+    }
+
+    /// Matches the given path (relative to the root and normalized to NFC).
+    fn matches(&self, rel_path: &str, is_dir: &dyn Fn() -> bool) -> bool {
+        let options = MatchOptions {
+            case_sensitive: self.case_sensitive,
+            ..MATCH_OPTIONS
+        };
+        self.pattern.matches_with(rel_path, options) && (!self.dir_only || is_dir())
+    }
+
+    pub(crate) fn load_rules(file_name: &Path) -> Result<Vec<GitRule>> {
+        let (lines, case_sensitive) = read_rules(file_name)?;
+        let mut rules = Vec::new();
+        for (line_number, line) in lines {
+            let rule = GitRule::try_from(line.as_str()).
+                with_context(|| format!("line {}: invalid rule '{}'", line_number, line))?;
+            rules.push(GitRule {
+                case_sensitive,
+                origin: Origin::File(file_name.to_path_buf(), line_number),
+                ..rule
+            });
+        }
+        Ok(rules)
+    }
+}
+
+impl TryFrom<&str> for GitRule {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let value = value.trim_end();
+        let text = value.to_owned();
+        let (include, value) = match value.strip_prefix('!') {
+            Some(value) => (true, value),
+            None => (false, value.strip_prefix('\\').unwrap_or(value))
+        };
+        let (dir_only, value) = match value.strip_suffix('/') {
+            Some(value) => (true, value),
+            None => (false, value)
+        };
+
+        let pattern = match value.strip_prefix('/') {
+            Some(anchored) => anchored.to_owned(),
+            None if value.contains('/') => value.to_owned(),
+            None => format!("**/{}", value)
+        };
+
+        Ok(GitRule {
+            pattern: Pattern::new(&normalize(&pattern))?,
+            include,
+            dir_only,
+            case_sensitive: true,
+            text,
+            origin: Origin::BuiltIn,
+        })
+    }
+}
+
+impl Display for GitRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let symbol = match self.include {
+            true => "+",
+            false => "-"
+        };
+        let suffix = match self.dir_only {
+            true => "/",
+            false => ""
+        };
+        write!(f, "{} {}{}", symbol, self.pattern.as_str(), suffix)
+    }
+}
+
+/// Path filter with `.gitignore` semantics: the last matching rule wins and files in an excluded
+/// directory cannot be re-included.
+#[derive(Debug)]
+pub struct GitPathFilter {
+    rules: Vec<GitRule>,
+    root: PathBuf,
+}
+
+impl GitPathFilter {
+    pub fn new(root: &Path, rules: Vec<GitRule>) -> GitPathFilter {
+        GitPathFilter {
+            rules,
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn load_from_file(root: &Path, file_name: &Path) -> Result<GitPathFilter> {
+        let rules = GitRule::load_rules(file_name)?;
+        Ok(GitPathFilter::new(root, rules))
+    }
+
+    /// Returns the last rule matching the given path, or `None` if no rule matches the path.
+    pub(crate) fn decide(&self, p: &Path, is_dir: &dyn Fn() -> bool) -> Option<&GitRule> {
+        let rel_path = p.strip_prefix(&self.root).ok()?;
+        self.last_match(&normalize(&rel_path.to_string_lossy()), is_dir)
+    }
+
+    fn last_match(&self, rel_path: &str, is_dir: &dyn Fn() -> bool) -> Option<&GitRule> {
+        self.rules.iter().
+            rev().
+            find(|r| r.matches(rel_path, is_dir))
+    }
+
+    /// Returns whether the given path is included together with the rule which decided it.
+    fn evaluate(&self, p: &Path) -> (bool, Option<RuleMatch>) {
+        let rel_path = match p.strip_prefix(&self.root) {
+            Ok(rel_path) => rel_path,
+            Err(_) => return (false, None)
+        };
+
+        let rel_path = normalize(&rel_path.to_string_lossy());
+        for (i, _) in rel_path.match_indices('/') {
+            if let Some(rule) = self.last_match(&rel_path[..i], &|| true).filter(|r| !r.include) {
+                return (false, Some(rule.to_match()));
+            }
+        }
+
+        let is_dir = || p.symlink_metadata().map(|m| m.is_dir()).unwrap_or(false);
+        match self.last_match(&rel_path, &is_dir) {
+            Some(rule) if !rel_path.is_empty() => (rule.include, Some(rule.to_match())),
+            _ => (true, None)
+        }
+    }
+}
+
+impl PathFilter for GitPathFilter {
+    fn matches(&self, p: &Path) -> bool {
+        self.evaluate(p).0
+    }
+
+    fn explain(&self, p: &Path) -> Option<RuleMatch> {
+        self.evaluate(p).1
+    }
+}
+
+/// Returns whether the given ignore file selects gitignore semantics by its first line.
+pub fn has_header(file_name: &Path) -> Result<bool> {
+    let mut reader = BufReader::new(File::open(file_name)?);
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+    Ok(first_line.trim() == GIT_FILTER_HEADER)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use indoc::indoc;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn given_filter(root: &Path, rules: &[&str]) -> Result<GitPathFilter> {
+        let rules = rules.iter().
+            map(|&r| GitRule::try_from(r)).
+            collect::<Result<Vec<GitRule>>>()?;
+        Ok(GitPathFilter::new(root, rules))
+    }
+
+    #[test]
+    fn test_matches_floating_and_anchored() -> Result<()> {
+        let filter = given_filter(Path::new("/some/path"), &["*.log", "/build", "doc/*.txt"])?;
+
+        assert_eq!(filter.matches(Path::new("/some/path/a.log")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/a/b/c.log")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/build")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/build/a.txt")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/a/build")), true);
+        assert_eq!(filter.matches(Path::new("/some/path/doc/a.txt")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/doc/a/b.txt")), true);
+        assert_eq!(filter.matches(Path::new("/some/path/a/doc/a.txt")), true);
+        assert_eq!(filter.matches(Path::new("/some/path/a.txt")), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_last_rule_wins() -> Result<()> {
+        let filter = given_filter(Path::new("/some/path"), &["*.txt", "!keep.txt", "a/keep.txt"])?;
+
+        assert_eq!(filter.matches(Path::new("/some/path/a.txt")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/keep.txt")), true);
+        assert_eq!(filter.matches(Path::new("/some/path/b/keep.txt")), true);
+        assert_eq!(filter.matches(Path::new("/some/path/a/keep.txt")), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_double_asterisk() -> Result<()> {
+        let filter = given_filter(Path::new("/some/path"), &["a/**/b", "c/**", "**/d/e"])?;
+
+        assert_eq!(filter.matches(Path::new("/some/path/a/b")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/a/x/y/b")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/c/x/y.txt")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/x/d/e")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/d/e")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/x/a/b")), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_no_reinclude_in_excluded_dir() -> Result<()> {
+        let filter = given_filter(Path::new("/some/path"), &["a/", "!a/b.txt"])?;
+
+        assert_eq!(filter.matches(Path::new("/some/path/a/b.txt")), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_dir_only() -> Result<()> {
+        let temp = tempdir()?;
+        fs::create_dir(temp.path().join("tmp"))?;
+        fs::create_dir(temp.path().join("x"))?;
+        fs::write(temp.path().join("x/tmp"), "file")?;
+        let filter = given_filter(temp.path(), &["tmp/"])?;
+
+        assert_eq!(filter.matches(&temp.path().join("tmp")), false);
+        assert_eq!(filter.matches(&temp.path().join("tmp/a.txt")), false);
+        assert_eq!(filter.matches(&temp.path().join("x/tmp")), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let temp = tempdir()?;
+        let file_name = temp.path().join(GIT_FILTER_FILENAME);
+        fs::write(&file_name, "a/\n*.log\n!keep.log\n")?;
+        let filter = GitPathFilter::load_from_file(temp.path(), &file_name)?;
+
+        assert_eq!(filter.explain(&temp.path().join("a/keep.log")), Some(RuleMatch {
+            origin: Origin::File(file_name.clone(), 1),
+            rule: String::from("a/"),
+            include: false,
+        }));
+        assert_eq!(filter.explain(&temp.path().join("b/keep.log")), Some(RuleMatch {
+            origin: Origin::File(file_name.clone(), 3),
+            rule: String::from("!keep.log"),
+            include: true,
+        }));
+        assert_eq!(filter.explain(&temp.path().join("b/x.txt")), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_case_insensitive_and_normalized() -> Result<()> {
+        let temp = tempdir()?;
+        let file_name = temp.path().join(GIT_FILTER_FILENAME);
+        fs::write(&file_name, "# auditr: case-insensitive\n*.jpg\n/r\u{e9}sum\u{e9}/\n")?;
+        let filter = GitPathFilter::load_from_file(temp.path(), &file_name)?;
+
+        assert!(!filter.matches(&temp.path().join("a/B.JPG")));
+        assert!(!filter.matches(&temp.path().join("Re\u{301}sume\u{301}/a.txt")));
+        assert!(filter.matches(&temp.path().join("a/b.png")));
+
+        let filter = given_filter(temp.path(), &["*.jpg"])?;
+        assert!(filter.matches(&temp.path().join("a/B.JPG")));
+
+        Ok(())
+    }
+
+    macro_rules! try_from_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() -> Result<()> {
+                let (input, expected) = $value;
+
+                let rule = GitRule::try_from(input)?;
+
+                assert_eq!(format!("{}", rule), expected);
+
+                Ok(())
+            }
+        )*
+        }
+    }
+
+    try_from_tests! {
+        test_try_from_floating: ("*.log", "- **/*.log"),
+        test_try_from_anchored: ("/build", "- build"),
+        test_try_from_nested: ("a/b", "- a/b"),
+        test_try_from_dir_only: ("tmp/", "- **/tmp/"),
+        test_try_from_negated: ("!a/*.txt ", "+ a/*.txt"),
+        test_try_from_escaped: ("\\!important", "- **/!important"),
+    }
+
+    #[test]
+    fn test_has_header() -> Result<()> {
+        let temp = tempdir()?;
+        fs::write(temp.path().join("git"), indoc!("
+            # auditr: gitignore
+            *.log
+        ").trim_start())?;
+        fs::write(temp.path().join("glob"), "*.log\n")?;
+
+        assert!(has_header(&temp.path().join("git"))?);
+        assert!(!has_header(&temp.path().join("glob"))?);
+
+        Ok(())
+    }
+}
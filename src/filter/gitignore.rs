@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+
+use crate::filter::gitfilter::{GitPathFilter, GitRule};
+use crate::filter::{PathFilter, RuleMatch};
+
+pub const GITIGNORE_FILENAME: &str = ".gitignore";
+
+/// Directories of version control systems, build outputs and package managers which are skipped
+/// when gitignore files are respected.
+const PRESET_RULES: [&str; 9] = [
+    ".git/", ".hg/", ".svn/", "target/", "node_modules/", "__pycache__/", ".venv/", ".tox/", ".gradle/"
+];
+
+/// Path filter honoring `.gitignore` files in the directory tree, the `.git/info/exclude` file of
+/// each repository in the tree, the global excludes file of git and a preset of directories which
+/// are usually not worth auditing. Like git, rules of deeper `.gitignore` files take precedence
+/// and files in an excluded directory cannot be re-included.
+pub struct GitignoreFilter {
+    root: PathBuf,
+    global: GitPathFilter,
+    dir_filters: RefCell<HashMap<PathBuf, Option<Rc<GitPathFilter>>>>,
+    error: RefCell<Option<anyhow::Error>>,
+}
+
+impl GitignoreFilter {
+    pub fn new(root: &Path) -> Result<GitignoreFilter> {
+        let mut rules = PRESET_RULES.iter().
+            map(|&r| GitRule::try_from(r)).
+            collect::<Result<Vec<GitRule>>>()?;
+
+        if let Some(file_name) = global_excludes_file().filter(|f| f.is_file()) {
+            rules.extend(GitRule::load_rules(&file_name).
+                with_context(|| format!("Invalid global excludes file '{}'", file_name.display()))?);
+        }
+
+        Ok(GitignoreFilter {
+            root: root.to_path_buf(),
+            global: GitPathFilter::new(root, rules),
+            dir_filters: RefCell::new(HashMap::new()),
+            error: RefCell::new(None),
+        })
+    }
+
+    fn dir_filter(&self, dir: &Path) -> Option<Rc<GitPathFilter>> {
+        if let Some(filter) = self.dir_filters.borrow().get(dir) {
+            return filter.clone();
+        }
+
+        let filter = match load_dir_rules(dir) {
+            Ok(rules) if rules.is_empty() => None,
+            Ok(rules) => Some(Rc::new(GitPathFilter::new(dir, rules))),
+            Err(err) => {
+                self.error.borrow_mut().get_or_insert(err);
+                None
+            }
+        };
+        self.dir_filters.borrow_mut().insert(dir.to_path_buf(), filter.clone());
+        filter
+    }
+
+    fn decide(&self, p: &Path, is_dir: &dyn Fn() -> bool) -> Option<RuleMatch> {
+        for dir in p.ancestors().skip(1).take_while(|d| d.starts_with(&self.root)) {
+            if let Some(rule) = self.dir_filter(dir).and_then(|f| f.decide(p, is_dir).map(GitRule::to_match)) {
+                return Some(rule);
+            }
+        }
+        self.global.decide(p, is_dir).map(GitRule::to_match)
+    }
+
+    /// Returns whether the given path is included together with the rule which decided it.
+    fn evaluate(&self, p: &Path) -> (bool, Option<RuleMatch>) {
+        if !p.starts_with(&self.root) {
+            return (false, None);
+        }
+
+        for dir in p.ancestors().skip(1).take_while(|&d| d != self.root && d.starts_with(&self.root)) {
+            if let Some(rule) = self.decide(dir, &|| true).filter(|r| !r.include) {
+                return (false, Some(rule));
+            }
+        }
+
+        let is_dir = || p.symlink_metadata().map(|m| m.is_dir()).unwrap_or(false);
+        match self.decide(p, &is_dir) {
+            Some(rule) if p != self.root => (rule.include, Some(rule)),
+            _ => (true, None)
+        }
+    }
+}
+
+impl PathFilter for GitignoreFilter {
+    fn matches(&self, p: &Path) -> bool {
+        self.evaluate(p).0
+    }
+
+    fn explain(&self, p: &Path) -> Option<RuleMatch> {
+        self.evaluate(p).1
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.error.borrow_mut().take() {
+            Some(err) => Err(err),
+            None => Ok(())
+        }
+    }
+}
+
+/// Loads the rules of the `.git/info/exclude` and `.gitignore` files of the given directory. Rules
+/// of `.gitignore` take precedence.
+fn load_dir_rules(dir: &Path) -> Result<Vec<GitRule>> {
+    let mut rules = Vec::new();
+    for file_name in [dir.join(".git/info/exclude"), dir.join(GITIGNORE_FILENAME)].iter() {
+        if file_name.is_file() {
+            rules.extend(GitRule::load_rules(file_name).
+                with_context(|| format!("Invalid ignore file '{}'", file_name.display()))?);
+        }
+    }
+    Ok(rules)
+}
+
+/// Returns the global excludes file of git as configured by `core.excludesFile`, falling back to
+/// `$XDG_CONFIG_HOME/git/ignore`.
+fn global_excludes_file() -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let config_home = env::var_os("XDG_CONFIG_HOME").
+        map(PathBuf::from).
+        or_else(|| home.as_ref().map(|h| h.join(".config")));
+
+    let configs = [home.as_ref().map(|h| h.join(".gitconfig")), config_home.as_ref().map(|c| c.join("git/config"))];
+    configs.iter().
+        flatten().
+        find_map(|c| read_excludes_file(c, home.as_deref())).
+        or_else(|| config_home.map(|c| c.join("git/ignore")))
+}
+
+/// Reads the value of `core.excludesFile` from the given git config file.
+fn read_excludes_file(config: &Path, home: Option<&Path>) -> Option<PathBuf> {
+    let contents = fs::read_to_string(config).ok()?;
+
+    let mut section = String::new();
+    let mut excludes_file = None;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_lowercase();
+            continue;
+        }
+
+        let mut kv = line.splitn(2, '=');
+        let (key, value) = match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim().trim_matches('"')),
+            _ => continue
+        };
+        if section == "core" && key.eq_ignore_ascii_case("excludesfile") {
+            excludes_file = match (value.strip_prefix("~/"), home) {
+                (Some(rel), Some(home)) => Some(home.join(rel)),
+                _ => Some(PathBuf::from(value))
+            };
+        }
+    }
+    excludes_file
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_matches() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        let root = temp.path();
+        fs::create_dir_all(root.join("a/.git/info"))?;
+        fs::create_dir_all(root.join("a/b/target"))?;
+        fs::write(root.join(GITIGNORE_FILENAME), "*.log\nbuild/\n")?;
+        fs::write(root.join("a").join(GITIGNORE_FILENAME), "!keep.log\n")?;
+        fs::write(root.join("a/.git/info/exclude"), "secret.txt\n")?;
+        fs::create_dir_all(root.join("build"))?;
+
+        // When
+        let filter = GitignoreFilter::new(root)?;
+
+        // Then
+        assert!(filter.matches(root));
+        assert!(filter.matches(&root.join("a/b/c.txt")));
+        assert!(!filter.matches(&root.join("x.log")));
+        assert!(!filter.matches(&root.join("a/b/x.log")));
+        assert!(filter.matches(&root.join("a/b/keep.log")));
+        assert!(!filter.matches(&root.join("build")));
+        assert!(!filter.matches(&root.join("build/keep.log")));
+        assert!(!filter.matches(&root.join("a/secret.txt")));
+        assert!(!filter.matches(&root.join("a/b/secret.txt")));
+        assert!(filter.matches(&root.join("secret.txt")));
+        assert!(!filter.matches(&root.join("a/.git")));
+        assert!(!filter.matches(&root.join("a/b/target")));
+        assert!(!filter.matches(&root.join("a/b/target/debug/out")));
+        assert!(filter.check().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_excludes_file() -> Result<()> {
+        let temp = tempdir()?;
+        let config = temp.path().join("config");
+        fs::write(&config, indoc!(r#"
+            [user]
+                excludesfile = /wrong
+            [core]
+                editor = vim
+                excludesFile = "~/.gitignore_global"
+        "#))?;
+
+        assert_eq!(read_excludes_file(&config, Some(Path::new("/home/user"))),
+                   Some(PathBuf::from("/home/user/.gitignore_global")));
+        assert_eq!(read_excludes_file(&temp.path().join("missing"), None), None);
+
+        Ok(())
+    }
+}
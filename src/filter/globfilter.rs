@@ -1,50 +1,145 @@
 use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use glob::Pattern;
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
 use lazy_static::lazy_static;
-
-use crate::filter::PathFilter;
-use crate::index::{HASH_INDEX_FILENAME, META_INDEX_FILENAME};
+use regex::{Regex, RegexBuilder};
+
+use crate::blocks::BLOCKS_DIR_NAME;
+use crate::chunks::CHUNKS_DIR_NAME;
+use crate::config::CONFIG_FILENAME;
+use crate::filter::gitfilter::GIT_FILTER_FILENAME;
+use crate::filter::metarule::MetaRule;
+use crate::filter::{normalize, read_rules, Origin, PathFilter, RuleMatch};
+use crate::index::{DIR_INDEX_FILENAME, HASH_INDEX_FILENAME, META_INDEX_FILENAME};
+use crate::journal::JOURNAL_FILENAME;
+use crate::parity::PARITY_DIR_NAME;
+use crate::signature::SIGNATURE_FILENAME;
 
 pub const GLOB_FILTER_FILENAME: &str = ".auditr-ignore";
 
+/// Prefix of rules matching paths by a regular expression instead of a glob pattern.
+const REGEX_PREFIX: &str = "re:";
+
+/// Matches paths by a glob pattern, by a regular expression (`re:` rules) or by their metadata.
+#[derive(Clone,Debug)]
+enum Matcher {
+    Glob(Pattern),
+    Regex(Regex),
+    Meta(MetaRule),
+}
+
+impl Matcher {
+    fn as_str(&self) -> &str {
+        match self {
+            Matcher::Glob(pattern) => pattern.as_str(),
+            Matcher::Regex(regex) => regex.as_str(),
+            Matcher::Meta(rule) => rule.as_str()
+        }
+    }
+}
+
 #[derive(Clone,Debug)]
 pub struct GlobRule {
-    pattern: glob::Pattern,
+    pattern: Matcher,
     include: bool,
+    case_sensitive: bool,
+    origin: Origin,
 }
 
 impl GlobRule {
-    fn new(pattern: &str, include: bool) -> Result<GlobRule> {
-        let pattern = Pattern::new(pattern)?;
+    pub fn new(pattern: &str, include: bool) -> Result<GlobRule> {
+        GlobRule::new_glob(pattern, include, true)
+    }
+
+    fn new_glob(pattern: &str, include: bool, case_sensitive: bool) -> Result<GlobRule> {
+        let pattern = Pattern::new(&normalize(pattern))?;
         Ok(GlobRule {
-            pattern,
+            pattern: Matcher::Glob(pattern),
             include,
+            case_sensitive,
+            origin: Origin::BuiltIn,
         })
     }
 
-    fn load_rules(file_name: &Path) -> Result<Vec<GlobRule>> {
-        let file = File::open(file_name)?;
-        let reader = BufReader::new(file);
+    /// Parses a rule with an optional leading `!` for inclusion.
+    fn parse(rule: &str, case_sensitive: bool) -> Result<GlobRule> {
+        let (include, rule) = match rule.strip_prefix('!') {
+            Some(rule) => (true, rule),
+            None => (false, rule)
+        };
 
-        let line_contains_filter = |l: &String| !l.starts_with('#') && !l.trim().is_empty();
-        let rules = reader.lines().
-            filter(|line| line.as_ref().map(line_contains_filter).unwrap_or(true)).
-            map(|line| GlobRule::try_from(line?.as_str())).
-            collect::<Result<Vec<GlobRule>>>()?;
+        if let Some(regex) = rule.strip_prefix(REGEX_PREFIX) {
+            let regex = RegexBuilder::new(&normalize(regex)).
+                case_insensitive(!case_sensitive).
+                build()?;
+            return Ok(GlobRule {
+                pattern: Matcher::Regex(regex),
+                include,
+                case_sensitive,
+                origin: Origin::BuiltIn,
+            });
+        }
+
+        match MetaRule::parse(rule) {
+            Some(meta) => Ok(GlobRule {
+                pattern: Matcher::Meta(meta?),
+                include,
+                case_sensitive,
+                origin: Origin::BuiltIn,
+            }),
+            None => GlobRule::new_glob(rule, include, case_sensitive)
+        }
+    }
+
+    pub fn with_origin(self, origin: Origin) -> GlobRule {
+        GlobRule {
+            origin,
+            ..self
+        }
+    }
 
-        let all_rules = DEFAULT_RULES.iter().
-            cloned().
-            chain(rules).
-            collect();
+    fn to_match(&self) -> RuleMatch {
+        // Patterns given on the command line are shown as typed
+        let prefix = match (self.include, &self.pattern) {
+            (_, Matcher::Glob(_)) if self.origin == Origin::CommandLine => "",
+            (true, Matcher::Regex(_)) => "!re:",
+            (false, Matcher::Regex(_)) => "re:",
+            (true, _) => "!",
+            (false, _) => ""
+        };
+        RuleMatch {
+            origin: self.origin.clone(),
+            rule: format!("{}{}", prefix, self.pattern.as_str()),
+            include: self.include,
+        }
+    }
+
+    /// Matches the given path (relative to the root and normalized to NFC).
+    fn matches(&self, rel_path: &str, metadata: &dyn Fn() -> Option<Metadata>) -> bool {
+        match &self.pattern {
+            Matcher::Glob(pattern) => pattern.matches_with(rel_path, MatchOptions {
+                case_sensitive: self.case_sensitive,
+                ..MatchOptions::new()
+            }),
+            Matcher::Regex(regex) => regex.is_match(rel_path),
+            Matcher::Meta(rule) => metadata().is_some_and(|m| rule.matches(&m))
+        }
+    }
 
-        Ok(all_rules)
+    fn load_rules(file_name: &Path) -> Result<Vec<GlobRule>> {
+        let (lines, case_sensitive) = read_rules(file_name)?;
+        let mut rules = Vec::new();
+        for (line_number, line) in lines {
+            let rule = GlobRule::parse(&line, case_sensitive).
+                with_context(|| format!("line {}: invalid rule '{}'", line_number, line))?;
+            rules.push(rule.with_origin(Origin::File(file_name.to_path_buf(), line_number)));
+        }
+        Ok(rules)
     }
 }
 
@@ -52,11 +147,7 @@ impl TryFrom<&str> for GlobRule {
     type Error = anyhow::Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if let Some(path) = value.strip_prefix('!') {
-            GlobRule::new(path, true)
-        } else {
-            GlobRule::new(value, false)
-        }
+        GlobRule::parse(value, true)
     }
 }
 
@@ -71,9 +162,9 @@ impl Display for GlobRule {
 }
 
 #[derive(Debug)]
-pub struct GlobPathFilter<'a> {
+pub struct GlobPathFilter {
     rules: Vec<GlobRule>,
-    root: &'a Path,
+    root: PathBuf,
     include_by_default: bool,
 }
 
@@ -81,19 +172,28 @@ lazy_static! {
     static ref DEFAULT_RULES: Vec<GlobRule> = vec![
         GlobRule::new(HASH_INDEX_FILENAME, false).unwrap(),
         GlobRule::new(META_INDEX_FILENAME, false).unwrap(),
+        GlobRule::new(DIR_INDEX_FILENAME, false).unwrap(),
+        GlobRule::new(SIGNATURE_FILENAME, false).unwrap(),
         GlobRule::new(GLOB_FILTER_FILENAME, false).unwrap(),
+        GlobRule::new(PARITY_DIR_NAME, false).unwrap(),
+        GlobRule::new(BLOCKS_DIR_NAME, false).unwrap(),
+        GlobRule::new(CHUNKS_DIR_NAME, false).unwrap(),
+        GlobRule::new(JOURNAL_FILENAME, false).unwrap(),
+        GlobRule::new(GIT_FILTER_FILENAME, false).unwrap(),
+        GlobRule::new(CONFIG_FILENAME, false).unwrap(),
     ];
 }
 
-impl GlobPathFilter<'_> {
+impl GlobPathFilter {
     pub fn new(root: &Path, rules: Vec<GlobRule>, include_by_default: bool) -> Result<GlobPathFilter> {
         Ok(GlobPathFilter {
             rules,
-            root,
+            root: root.to_path_buf(),
             include_by_default,
         })
     }
 
+    /// Returns a filter excluding the files maintained by auditr in the given root directory.
     pub fn default(root: &Path) -> Result<GlobPathFilter> {
         GlobPathFilter::new(root, DEFAULT_RULES.clone(), true)
     }
@@ -104,17 +204,39 @@ impl GlobPathFilter<'_> {
     }
 }
 
-impl PathFilter for GlobPathFilter<'_> {
-    fn matches(&self, p: &Path) -> bool {
-        if let Ok(rel_path) = p.strip_prefix(self.root) {
-            let rule = self.rules.iter().find(|i| i.pattern.matches_path(rel_path));
-            return match rule {
+impl GlobPathFilter {
+    fn evaluate(&self, p: &Path, metadata: &dyn Fn() -> Option<Metadata>) -> bool {
+        if p.strip_prefix(&self.root).is_ok() {
+            return match self.matching_rule(p, metadata) {
                 Some(rule) => rule.include,
                 None => self.include_by_default
             };
         }
         false
     }
+
+    fn matching_rule(&self, p: &Path, metadata: &dyn Fn() -> Option<Metadata>) -> Option<&GlobRule> {
+        let rel_path = normalize(&p.strip_prefix(&self.root).ok()?.to_string_lossy());
+        self.rules.iter().find(|i| i.matches(&rel_path, metadata))
+    }
+}
+
+impl PathFilter for GlobPathFilter {
+    fn matches(&self, p: &Path) -> bool {
+        self.evaluate(p, &|| p.symlink_metadata().ok())
+    }
+
+    fn matches_with_metadata(&self, p: &Path, metadata: &Metadata) -> bool {
+        self.evaluate(p, &|| Some(metadata.clone()))
+    }
+
+    fn matches_path(&self, p: &Path) -> bool {
+        self.evaluate(p, &|| None)
+    }
+
+    fn explain(&self, p: &Path) -> Option<RuleMatch> {
+        self.matching_rule(p, &|| p.symlink_metadata().ok()).map(GlobRule::to_match)
+    }
 }
 
 #[cfg(test)]
@@ -176,6 +298,127 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_matches_meta_rules() -> Result<()> {
+        let temp = tempdir()?;
+        fs::write(temp.path().join("keep.bin"), vec![0; 2048])?;
+        fs::write(temp.path().join("large.bin"), vec![0; 2048])?;
+        fs::write(temp.path().join("small.bin"), vec![0; 16])?;
+
+        let patterns = vec![
+            GlobRule::try_from("!keep.bin")?,
+            GlobRule::try_from("size>1K")?,
+        ];
+        let filter = GlobPathFilter::new(temp.path(), patterns, true)?;
+
+        assert_eq!(filter.matches(&temp.path().join("keep.bin")), true);
+        assert_eq!(filter.matches(&temp.path().join("large.bin")), false);
+        assert_eq!(filter.matches(&temp.path().join("small.bin")), true);
+        assert_eq!(filter.matches(&temp.path().join("missing.bin")), true);
+
+        let metadata = fs::symlink_metadata(temp.path().join("small.bin"))?;
+        assert_eq!(filter.matches_with_metadata(&temp.path().join("large.bin"), &metadata), true);
+        assert_eq!(filter.matches_path(&temp.path().join("large.bin")), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_regex_rules() -> Result<()> {
+        let patterns = vec![
+            GlobRule::try_from("!re:^keep/")?,
+            GlobRule::try_from("re:\\.(log|tmp)$")?,
+        ];
+        let filter = GlobPathFilter::new(Path::new("/some/path"), patterns, true)?;
+
+        assert_eq!(filter.matches(Path::new("/some/path/a/b.log")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/b.tmp")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/keep/b.log")), true);
+        assert_eq!(filter.matches(Path::new("/some/path/b.txt")), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_case_insensitive() -> Result<()> {
+        let temp = tempdir()?;
+        fs::write(temp.path().join(GLOB_FILTER_FILENAME), indoc!("
+            # auditr: case-insensitive
+            *.jpg
+            re:^raw/
+        "))?;
+        let filter = GlobPathFilter::load_from_path(temp.path(), true)?;
+
+        assert_eq!(filter.matches(&temp.path().join("a.JPG")), false);
+        assert_eq!(filter.matches(&temp.path().join("a.jpg")), false);
+        assert_eq!(filter.matches(&temp.path().join("RAW/a.txt")), false);
+        assert_eq!(filter.matches(&temp.path().join("a.png")), true);
+
+        let filter = GlobPathFilter::new(temp.path(), vec![GlobRule::try_from("*.jpg")?], true)?;
+        assert_eq!(filter.matches(&temp.path().join("a.JPG")), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_unicode_normalization() -> Result<()> {
+        let patterns = vec![
+            GlobRule::try_from("caf\u{e9}/*")?,
+            GlobRule::try_from("re:^cre\u{300}me")?,
+        ];
+        let filter = GlobPathFilter::new(Path::new("/some/path"), patterns, true)?;
+
+        assert_eq!(filter.matches(Path::new("/some/path/cafe\u{301}/a.txt")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/caf\u{e9}/a.txt")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/cr\u{e8}me.txt")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/cafe/a.txt")), true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_rules_invalid() -> Result<()> {
+        let temp = tempdir()?;
+
+        let path = temp.path().join(".auditr-ignore");
+        let rules_file = indoc!("
+            # some comment
+            re:^valid/
+            re:(unclosed
+        ");
+        fs::write(path.as_path(), rules_file)?;
+
+        let result = GlobRule::load_rules(path.as_path());
+
+        assert_eq!(result.map(|_| ()).map_err(|e| e.to_string()),
+                   Err(String::from("line 3: invalid rule 're:(unclosed'")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let temp = tempdir()?;
+
+        let path = temp.path().join(".auditr-ignore");
+        fs::write(path.as_path(), "# comment\n!some/dir/file.txt\nre:^some/\n")?;
+        let filter = GlobPathFilter::load_from_path(temp.path(), true)?;
+
+        assert_eq!(filter.explain(&temp.path().join("some/dir/file.txt")), Some(RuleMatch {
+            origin: Origin::File(path.clone(), 2),
+            rule: String::from("!some/dir/file.txt"),
+            include: true,
+        }));
+        assert_eq!(filter.explain(&temp.path().join("some/other.txt")), Some(RuleMatch {
+            origin: Origin::File(path.clone(), 3),
+            rule: String::from("re:^some/"),
+            include: false,
+        }));
+        assert_eq!(filter.explain(&temp.path().join("other.txt")), None);
+
+        Ok(())
+    }
+
     macro_rules! try_from_tests {
         ($($name:ident: $value:expr,)*) => {
         $(
@@ -197,6 +440,8 @@ mod tests {
     try_from_tests! {
         test_try_from_include: ("!**/test.txt", true, "**/test.txt"),
         test_try_from_exclude: ("**/test.txt", false, "**/test.txt"),
+        test_try_from_meta: ("!type:dir", true, "type:dir"),
+        test_try_from_regex: ("re:\\.(jpe?g|png)$", false, "\\.(jpe?g|png)$"),
     }
 
     #[test]
@@ -216,6 +461,11 @@ mod tests {
         assert_eq!(filter.matches(Path::new("/some/path/test.txt")), true);
         assert_eq!(filter.matches(Path::new("/some/path/.auditr-meta")), false);
         assert_eq!(filter.matches(Path::new("/some/path/.auditr-sha256")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/.auditr-dirs")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/.auditr-sig")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/.auditr-parity")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/.auditr-blocks")), false);
+        assert_eq!(filter.matches(Path::new("/some/path/.auditr-chunks")), false);
         assert_eq!(filter.matches(Path::new("/some/path/dir/.auditr-meta")), true);
         assert_eq!(filter.matches(Path::new("/some/path/dir/.auditr-sha256")), true);
 
@@ -238,17 +488,11 @@ mod tests {
 
         let rules = GlobRule::load_rules(path.as_path())?;
 
-        assert_eq!(rules.len(), 5);
-        assert_eq!(rules[0].pattern.as_str(), HASH_INDEX_FILENAME);
-        assert_eq!(rules[0].include, false);
-        assert_eq!(rules[1].pattern.as_str(), META_INDEX_FILENAME);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].pattern.as_str(), "some/dir/file.txt");
+        assert_eq!(rules[0].include, true);
+        assert_eq!(rules[1].pattern.as_str(), "some/dir/*");
         assert_eq!(rules[1].include, false);
-        assert_eq!(rules[2].pattern.as_str(), GLOB_FILTER_FILENAME);
-        assert_eq!(rules[2].include, false);
-        assert_eq!(rules[3].pattern.as_str(), "some/dir/file.txt");
-        assert_eq!(rules[3].include, true);
-        assert_eq!(rules[4].pattern.as_str(), "some/dir/*");
-        assert_eq!(rules[4].include, false);
 
         Ok(())
     }
@@ -269,7 +513,7 @@ mod tests {
         let filter = GlobPathFilter::load_from_path(temp.path(), true)?;
 
         // Then
-        assert_eq!(filter.rules.len(), 5);
+        assert_eq!(filter.rules.len(), 2);
 
         Ok(())
     }
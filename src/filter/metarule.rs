@@ -0,0 +1,208 @@
+use std::cmp::Ordering;
+use std::fs::Metadata;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Result};
+
+/// A rule matching files by their metadata instead of by their path, e.g. `size>50G`,
+/// `mtime<10m` or `type:symlink`. Size and modification time rules only match files, so that
+/// directories are still descended into. Rules are evaluated against the metadata of the walked
+/// entry itself, so `type:symlink` matches symbolic links, which are never followed.
+#[derive(Clone, Debug)]
+pub struct MetaRule {
+    condition: Condition,
+    text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    Size(Ordering, u64),
+    Age(Ordering, u64),
+    Type(FileKind),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FileKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+impl MetaRule {
+    /// Parses a metadata rule. Returns `None` if the given rule is not a metadata rule.
+    pub fn parse(rule: &str) -> Option<Result<MetaRule>> {
+        let condition = if let Some(kind) = rule.strip_prefix("type:") {
+            parse_kind(kind).map(Condition::Type)
+        } else if let Some((ordering, value)) = parse_comparison(rule, "size") {
+            parse_size(value).map(|size| Condition::Size(ordering, size))
+        } else if let Some((ordering, value)) = parse_comparison(rule, "mtime") {
+            parse_duration(value).map(|age| Condition::Age(ordering, age))
+        } else {
+            return None;
+        };
+
+        Some(condition.map(|condition| MetaRule {
+            condition,
+            text: rule.to_owned(),
+        }))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let file_type = metadata.file_type();
+        match self.condition {
+            Condition::Type(FileKind::File) => file_type.is_file(),
+            Condition::Type(FileKind::Dir) => file_type.is_dir(),
+            Condition::Type(FileKind::Symlink) => file_type.is_symlink(),
+            _ if file_type.is_dir() => false,
+            Condition::Size(ordering, size) => metadata.len().cmp(&size) == ordering,
+            Condition::Age(ordering, age) => {
+                let actual = metadata.modified().ok().
+                    and_then(|m| SystemTime::now().duration_since(m).ok()).
+                    map_or(0, |d| d.as_secs());
+                actual.cmp(&age) == ordering
+            }
+        }
+    }
+}
+
+fn parse_comparison<'a>(rule: &'a str, name: &str) -> Option<(Ordering, &'a str)> {
+    let rest = rule.strip_prefix(name)?;
+    if let Some(value) = rest.strip_prefix('<') {
+        Some((Ordering::Less, value))
+    } else {
+        rest.strip_prefix('>').map(|value| (Ordering::Greater, value))
+    }
+}
+
+fn parse_kind(kind: &str) -> Result<FileKind> {
+    match kind {
+        "file" => Ok(FileKind::File),
+        "dir" => Ok(FileKind::Dir),
+        "symlink" => Ok(FileKind::Symlink),
+        _ => bail!("invalid file type '{}', expected file, dir or symlink", kind)
+    }
+}
+
+/// Parses a size with an optional binary unit suffix (K, M, G, T).
+fn parse_size(value: &str) -> Result<u64> {
+    let (number, unit) = split_unit(value);
+    let factor = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("invalid size '{}', expected a number with an optional unit K, M, G or T", value)
+    };
+    parse_number(number, value)?.checked_mul(factor).ok_or_else(|| anyhow!("size '{}' is too large", value))
+}
+
+/// Parses a duration with an optional unit suffix (s, m, h, d, w) into seconds.
+fn parse_duration(value: &str) -> Result<u64> {
+    let (number, unit) = split_unit(value);
+    let factor = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!("invalid duration '{}', expected a number with an optional unit s, m, h, d or w", value)
+    };
+    parse_number(number, value)?.checked_mul(factor).ok_or_else(|| anyhow!("duration '{}' is too large", value))
+}
+
+fn split_unit(value: &str) -> (&str, &str) {
+    let i = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    value.split_at(i)
+}
+
+fn parse_number(number: &str, value: &str) -> Result<u64> {
+    number.parse::<u64>().map_err(|_| anyhow!("invalid number in '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::File;
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn parse(rule: &str) -> Result<MetaRule> {
+        MetaRule::parse(rule).unwrap()
+    }
+
+    macro_rules! parse_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (input, expected) = $value;
+                let rule = MetaRule::parse(input).map(|r| r.map(|r| r.condition).map_err(|e| e.to_string()));
+                assert_eq!(rule, expected);
+            }
+        )*
+        }
+    }
+
+    parse_tests! {
+        test_parse_size: ("size>50G", Some(Ok(Condition::Size(Ordering::Greater, 50 << 30)))),
+        test_parse_size_bytes: ("size<100", Some(Ok(Condition::Size(Ordering::Less, 100)))),
+        test_parse_size_invalid_unit: ("size>5X", Some(Err(String::from(
+            "invalid size '5X', expected a number with an optional unit K, M, G or T")))),
+        test_parse_mtime: ("mtime<10m", Some(Ok(Condition::Age(Ordering::Less, 600)))),
+        test_parse_mtime_invalid: ("mtime<m", Some(Err(String::from("invalid number in 'm'")))),
+        test_parse_type: ("type:dir", Some(Ok(Condition::Type(FileKind::Dir)))),
+        test_parse_type_symlink: ("type:symlink", Some(Ok(Condition::Type(FileKind::Symlink)))),
+        test_parse_type_invalid: ("type:pipe", Some(Err(String::from(
+            "invalid file type 'pipe', expected file, dir or symlink")))),
+        test_parse_glob: ("sizes/*.txt", None),
+        test_parse_glob_mtime: ("mtime.txt", None),
+    }
+
+    #[test]
+    fn test_matches() -> Result<()> {
+        let temp = tempdir()?;
+        fs::write(temp.path().join("large"), vec![0; 2048])?;
+        fs::write(temp.path().join("old"), "old")?;
+        File::options().write(true).open(temp.path().join("old"))?.
+            set_modified(SystemTime::now() - Duration::from_secs(3600))?;
+        let large = fs::symlink_metadata(temp.path().join("large"))?;
+        let old = fs::symlink_metadata(temp.path().join("old"))?;
+        let dir = fs::symlink_metadata(temp.path())?;
+
+        assert!(parse("size>1K")?.matches(&large));
+        assert!(!parse("size>1K")?.matches(&old));
+        assert!(!parse("size<1K")?.matches(&dir));
+        assert!(parse("mtime<10m")?.matches(&large));
+        assert!(!parse("mtime<10m")?.matches(&old));
+        assert!(parse("mtime>10m")?.matches(&old));
+        assert!(!parse("mtime<10m")?.matches(&dir));
+        assert!(parse("type:file")?.matches(&old));
+        assert!(parse("type:dir")?.matches(&dir));
+        assert!(!parse("type:dir")?.matches(&old));
+        assert!(!parse("type:symlink")?.matches(&old));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_matches_symlink() -> Result<()> {
+        let temp = tempdir()?;
+        fs::write(temp.path().join("file"), "file")?;
+        std::os::unix::fs::symlink(temp.path().join("file"), temp.path().join("link"))?;
+        let link = fs::symlink_metadata(temp.path().join("link"))?;
+
+        assert!(parse("type:symlink")?.matches(&link));
+        assert!(!parse("type:file")?.matches(&link));
+
+        Ok(())
+    }
+}
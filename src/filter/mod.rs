@@ -1,26 +1,107 @@
-use std::path::Path;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs::{File, Metadata};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use unicode_normalization::UnicodeNormalization;
 
-use crate::filter::globfilter::{GLOB_FILTER_FILENAME, GlobPathFilter};
-use crate::index::{HASH_INDEX_FILENAME, META_INDEX_FILENAME};
+use crate::filter::combinators::{And, Not, Or};
+use crate::filter::gitfilter::{GIT_FILTER_FILENAME, GitPathFilter};
+use crate::filter::gitignore::GitignoreFilter;
+use crate::filter::globfilter::{GLOB_FILTER_FILENAME, GlobPathFilter, GlobRule};
+use crate::filter::nested::NestedPathFilter;
+use crate::blocks::BLOCKS_DIR_NAME;
+use crate::chunks::CHUNKS_DIR_NAME;
+use crate::config::CONFIG_FILENAME;
+use crate::index::{DIR_INDEX_FILENAME, HASH_INDEX_FILENAME, META_INDEX_FILENAME};
+use crate::journal::JOURNAL_FILENAME;
+use crate::parity::PARITY_DIR_NAME;
+use crate::signature::SIGNATURE_FILENAME;
 
+pub mod combinators;
+pub mod gitfilter;
+pub mod gitignore;
 pub mod globfilter;
+pub mod metarule;
+pub mod nested;
+
+/// Comment line of an ignore file which makes all its rules match case-insensitively.
+pub const CASE_INSENSITIVE_DIRECTIVE: &str = "# auditr: case-insensitive";
+
+/// Where a filter rule was defined.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Origin {
+    BuiltIn,
+    CommandLine,
+    /// An ignore file and the line number of the rule.
+    File(PathBuf, usize),
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::BuiltIn => write!(f, "built-in rule"),
+            Origin::CommandLine => write!(f, "command line"),
+            Origin::File(file_name, line) => write!(f, "{}:{}", file_name.display(), line)
+        }
+    }
+}
+
+/// The rule which decided whether a path is included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleMatch {
+    pub origin: Origin,
+    pub rule: String,
+    pub include: bool,
+}
 
 pub trait PathFilter {
     fn matches(&self, p: &Path) -> bool;
+
+    /// Returns the rule which decided whether the given path is included, or `None` if no rule
+    /// matches the path.
+    fn explain(&self, _p: &Path) -> Option<RuleMatch> {
+        None
+    }
+
+    /// Same as `matches`, but with the metadata of the path already read while walking the
+    /// directory tree. Filters evaluating metadata override this to avoid reading it again.
+    fn matches_with_metadata(&self, p: &Path, _metadata: &Metadata) -> bool {
+        self.matches(p)
+    }
+
+    /// Same as `matches`, but ignores rules evaluating metadata. Used for paths which are not
+    /// walked, like the entries of an existing index.
+    fn matches_path(&self, p: &Path) -> bool {
+        self.matches(p)
+    }
+
+    /// Returns the first error which occurred while matching paths, e.g. an invalid ignore file
+    /// in a sub directory.
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub struct DefaultPathFilter {
-    excluded: [String; 2]
+    excluded: [String; 9]
 }
 
 impl DefaultPathFilter {
     pub fn new(dir_name: &Path) -> DefaultPathFilter {
         let hash_idx_path = dir_name.join(Path::new(HASH_INDEX_FILENAME)).to_string_lossy().to_string();
         let meta_idx_path = dir_name.join(Path::new(META_INDEX_FILENAME)).to_string_lossy().to_string();
+        let dir_idx_path = dir_name.join(Path::new(DIR_INDEX_FILENAME)).to_string_lossy().to_string();
+        let sig_path = dir_name.join(Path::new(SIGNATURE_FILENAME)).to_string_lossy().to_string();
+        let parity_path = dir_name.join(Path::new(PARITY_DIR_NAME)).to_string_lossy().to_string();
+        let blocks_path = dir_name.join(Path::new(BLOCKS_DIR_NAME)).to_string_lossy().to_string();
+        let chunks_path = dir_name.join(Path::new(CHUNKS_DIR_NAME)).to_string_lossy().to_string();
+        let journal_path = dir_name.join(Path::new(JOURNAL_FILENAME)).to_string_lossy().to_string();
+        let config_path = dir_name.join(Path::new(CONFIG_FILENAME)).to_string_lossy().to_string();
         DefaultPathFilter {
-            excluded: [hash_idx_path, meta_idx_path]
+            excluded: [hash_idx_path, meta_idx_path, dir_idx_path, sig_path, parity_path, blocks_path, chunks_path, journal_path, config_path]
         }
     }
 }
@@ -29,15 +110,135 @@ impl PathFilter for DefaultPathFilter {
     fn matches(&self, p: &Path) -> bool {
         !self.excluded.contains(&p.to_string_lossy().to_string())
     }
+
+    fn explain(&self, p: &Path) -> Option<RuleMatch> {
+        if self.matches(p) {
+            return None;
+        }
+
+        Some(RuleMatch {
+            origin: Origin::BuiltIn,
+            rule: p.file_name()?.to_string_lossy().to_string(),
+            include: false,
+        })
+    }
+}
+
+/// Explains the result of filters which must all include a path: the first filter excluding the
+/// path decides, otherwise the last filter with a matching rule.
+fn explain_all<'f>(filters: impl Iterator<Item=&'f (dyn PathFilter + 'f)>, p: &Path) -> Option<RuleMatch> {
+    let mut explanation = None;
+    for filter in filters {
+        if !filter.matches(p) {
+            return filter.explain(p);
+        }
+        explanation = filter.explain(p).or(explanation);
+    }
+    explanation
 }
 
+/// Options for filtering the files of a directory tree in addition to its ignore files.
+#[derive(Clone, Debug, Default)]
+pub struct FilterOptions {
+    /// Honor `.gitignore` files and skip common version control and build directories.
+    pub respect_gitignore: bool,
+    /// Glob patterns of paths to exclude in addition to the ignore files.
+    pub exclude: Vec<String>,
+    /// Glob patterns of paths to include even if they are excluded otherwise.
+    pub include: Vec<String>,
+}
+
+/// Loads the filter of the given directory tree, consisting of the ignore file of the root
+/// directory and the ignore files of all sub directories.
 pub fn load_filter<'a>(path: &'a Path) -> Result<Box<dyn PathFilter + 'a>>{
-    if path.join(GLOB_FILTER_FILENAME).exists() {
-        let filter = GlobPathFilter::load_from_path(path, true)?;
-        return Ok(Box::new(filter));
+    load_filter_with(path, &FilterOptions::default())
+}
+
+/// Loads the filter of the given directory tree and combines it with the filters selected by the
+/// given options. Paths matching an include pattern are included, otherwise paths matching an
+/// exclude pattern are excluded, otherwise the ignore files decide. Files maintained by auditr
+/// in the root directory are always excluded.
+pub fn load_filter_with<'a>(path: &'a Path, options: &FilterOptions) -> Result<Box<dyn PathFilter + 'a>>{
+    let root_filter: Box<dyn PathFilter> = match load_dir_filter(path)? {
+        Some(filter) => filter,
+        None => Box::new(GlobPathFilter::new(path, Vec::new(), true)?)
+    };
+    let nested = Box::new(NestedPathFilter::new(path, root_filter));
+    let mut filter: Box<dyn PathFilter> = Box::new(And::new(vec![Box::new(GlobPathFilter::default(path)?), nested]));
+
+    if options.respect_gitignore {
+        filter = Box::new(And::new(vec![filter, Box::new(GitignoreFilter::new(path)?)]));
+    }
+
+    if !options.exclude.is_empty() {
+        let excluded = Box::new(Not::new(any_glob(path, &options.exclude)?));
+        filter = Box::new(And::new(vec![excluded, filter]));
+    }
+
+    if !options.include.is_empty() {
+        let included = Or::new(vec![any_glob(path, &options.include)?, filter]);
+        filter = Box::new(And::new(vec![Box::new(GlobPathFilter::default(path)?), Box::new(included)]));
+    }
+
+    Ok(filter)
+}
+
+/// Returns a filter which includes exactly the paths matching any of the given glob patterns.
+fn any_glob(path: &Path, patterns: &[String]) -> Result<Box<dyn PathFilter>> {
+    let rules = patterns.iter().
+        map(|p| GlobRule::new(p, true).
+            map(|r| r.with_origin(Origin::CommandLine)).
+            with_context(|| format!("Invalid pattern '{}'", p))).
+        collect::<Result<Vec<GlobRule>>>()?;
+    Ok(Box::new(GlobPathFilter::new(path, rules, false)?))
+}
+
+/// Normalizes a pattern or path to NFC, so that NFD paths (e.g. created by macOS clients) match NFC
+/// patterns and vice versa. Filters always match in NFC, independently of the normalization form
+/// selected for `Entry::norm_path`, so that the same files are walked under every form.
+pub(crate) fn normalize(s: &str) -> String {
+    s.nfc().collect()
+}
+
+/// Reads the rules of an ignore file together with their line numbers, skipping comments and
+/// empty lines. Returns whether the rules are case-sensitive as well.
+pub(crate) fn read_rules(file_name: &Path) -> Result<(Vec<(usize, String)>, bool)> {
+    let reader = BufReader::new(File::open(file_name)?);
+
+    let mut rules = Vec::new();
+    let mut case_sensitive = true;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim() == CASE_INSENSITIVE_DIRECTIVE {
+            case_sensitive = false;
+        } else if !line.starts_with('#') && !line.trim().is_empty() {
+            rules.push((i + 1, line));
+        }
     }
+    Ok((rules, case_sensitive))
+}
 
-    Ok(Box::new(DefaultPathFilter::new(path)))
+/// Loads the ignore file of a single directory, if any.
+fn load_dir_filter(path: &Path) -> Result<Option<Box<dyn PathFilter>>> {
+    if path.join(GIT_FILTER_FILENAME).exists() {
+        let filter = GitPathFilter::load_from_file(path, &path.join(GIT_FILTER_FILENAME)).
+            with_context(|| format!("Invalid ignore file '{}'", path.join(GIT_FILTER_FILENAME).display()))?;
+        return Ok(Some(Box::new(filter)));
+    }
+
+    let file_name = path.join(GLOB_FILTER_FILENAME);
+    if !file_name.exists() {
+        return Ok(None);
+    }
+
+    let filter: Box<dyn PathFilter> = if gitfilter::has_header(&file_name)? {
+        Box::new(GitPathFilter::load_from_file(path, &file_name).
+            with_context(|| format!("Invalid ignore file '{}'", file_name.display()))?)
+    } else {
+        Box::new(GlobPathFilter::load_from_path(path, true).
+            with_context(|| format!("Invalid ignore file '{}'", file_name.display()))?)
+    };
+    Ok(Some(filter))
 }
 
 #[cfg(test)]
@@ -69,6 +270,13 @@ mod tests {
         test_hash_index_relative_path: (Path::new(HASH_INDEX_FILENAME), true),
         test_meta_abs_path: (&Path::new("/some/path").join(META_INDEX_FILENAME), false),
         test_hash_abs_path: (&Path::new("/some/path").join(HASH_INDEX_FILENAME), false),
+        test_dir_abs_path: (&Path::new("/some/path").join(DIR_INDEX_FILENAME), false),
+        test_sig_abs_path: (&Path::new("/some/path").join(SIGNATURE_FILENAME), false),
+        test_parity_abs_path: (&Path::new("/some/path").join(PARITY_DIR_NAME), false),
+        test_blocks_abs_path: (&Path::new("/some/path").join(BLOCKS_DIR_NAME), false),
+        test_chunks_abs_path: (&Path::new("/some/path").join(CHUNKS_DIR_NAME), false),
+        test_journal_abs_path: (&Path::new("/some/path").join(JOURNAL_FILENAME), false),
+        test_config_abs_path: (&Path::new("/some/path").join(CONFIG_FILENAME), false),
     }
 
     #[test]
@@ -92,10 +300,116 @@ mod tests {
         assert_eq!(filter.matches(&temp.path().join("yet/another.txt")), true);
         assert_eq!(filter.matches(&temp.path().join(META_INDEX_FILENAME)), false);
         assert_eq!(filter.matches(&temp.path().join(HASH_INDEX_FILENAME)), false);
+        assert_eq!(filter.matches(&temp.path().join(DIR_INDEX_FILENAME)), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_filter_gitignore_header() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+
+        let path = temp.path().join(GLOB_FILTER_FILENAME);
+        let rules_file = indoc!("
+            # auditr: gitignore
+            dir/
+            !file.txt
+        ");
+        fs::write(path.as_path(), rules_file.trim_start())?;
+
+        // When
+        let filter = load_filter(temp.path())?;
+
+        // Then
+        assert_eq!(filter.matches(&temp.path().join("some/dir/file.txt")), false);
+        assert_eq!(filter.matches(&temp.path().join("some/file.txt")), true);
+        assert_eq!(filter.matches(&temp.path().join(GLOB_FILTER_FILENAME)), false);
 
         Ok(())
     }
 
+    #[test]
+    fn test_load_filter_gitignore_file() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::write(temp.path().join(GLOB_FILTER_FILENAME), "*.txt\n")?;
+        fs::write(temp.path().join(GIT_FILTER_FILENAME), "*.log\n")?;
+
+        // When
+        let filter = load_filter(temp.path())?;
+
+        // Then
+        assert_eq!(filter.matches(&temp.path().join("a/b.log")), false);
+        assert_eq!(filter.matches(&temp.path().join("a/b.txt")), true);
+        assert_eq!(filter.matches(&temp.path().join(GIT_FILTER_FILENAME)), false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_filter_reserved_only_in_root() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::create_dir_all(temp.path().join("a"))?;
+        fs::create_dir_all(temp.path().join("b"))?;
+        fs::write(temp.path().join(GLOB_FILTER_FILENAME), "# auditr: gitignore\n!*\n")?;
+        fs::write(temp.path().join("a").join(GLOB_FILTER_FILENAME), "*.log\n")?;
+
+        // When
+        let filter = load_filter(temp.path())?;
+
+        // Then
+        assert!(!filter.matches(&temp.path().join(HASH_INDEX_FILENAME)));
+        assert!(!filter.matches(&temp.path().join(GLOB_FILTER_FILENAME)));
+        assert!(filter.matches(&temp.path().join("a").join(HASH_INDEX_FILENAME)));
+        assert!(filter.matches(&temp.path().join("b").join(HASH_INDEX_FILENAME)));
+        assert_eq!(filter.explain(&temp.path().join(HASH_INDEX_FILENAME)), Some(RuleMatch {
+            origin: Origin::BuiltIn,
+            rule: String::from(HASH_INDEX_FILENAME),
+            include: false,
+        }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_filter_with_include_exclude() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::write(temp.path().join(GLOB_FILTER_FILENAME), "*.log\n")?;
+        let options = FilterOptions {
+            exclude: vec![String::from("a/*"), String::from("*.tmp")],
+            include: vec![String::from("a/keep.*"), String::from(".auditr-*")],
+            ..FilterOptions::default()
+        };
+
+        // When
+        let filter = load_filter_with(temp.path(), &options)?;
+
+        // Then
+        assert!(!filter.matches(&temp.path().join("x.log")));
+        assert!(!filter.matches(&temp.path().join("x.tmp")));
+        assert!(!filter.matches(&temp.path().join("a/x.txt")));
+        assert!(filter.matches(&temp.path().join("a/keep.log")));
+        assert!(filter.matches(&temp.path().join("b/x.txt")));
+        assert!(!filter.matches(&temp.path().join(HASH_INDEX_FILENAME)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_filter_with_invalid_pattern() {
+        let options = FilterOptions {
+            exclude: vec![String::from("[")],
+            ..FilterOptions::default()
+        };
+
+        let result = load_filter_with(Path::new("/some/path"), &options);
+
+        assert_eq!(result.err().map(|e| e.to_string()), Some(String::from("Invalid pattern '['")));
+    }
+
     #[test]
     fn test_load_filter_no_ignorefile() -> Result<()> {
         // Given
@@ -108,6 +422,7 @@ mod tests {
         assert_eq!(filter.matches(&temp.path().join("some/dir/other.txt")), true);
         assert_eq!(filter.matches(&temp.path().join(META_INDEX_FILENAME)), false);
         assert_eq!(filter.matches(&temp.path().join(HASH_INDEX_FILENAME)), false);
+        assert_eq!(filter.matches(&temp.path().join(DIR_INDEX_FILENAME)), false);
 
         Ok(())
     }
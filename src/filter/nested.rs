@@ -0,0 +1,182 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::Result;
+
+use crate::filter::{explain_all, load_dir_filter, PathFilter, RuleMatch};
+
+/// Applies the ignore files of sub directories to their subtrees in addition to the filter of the
+/// root directory. A path is only included if the root filter and the filters of all its parent
+/// directories include it. The ignore file of a sub directory is loaded when the directory is
+/// first entered.
+pub struct NestedPathFilter<'a> {
+    root: PathBuf,
+    root_filter: Box<dyn PathFilter + 'a>,
+    dir_filters: RefCell<HashMap<PathBuf, Option<Rc<dyn PathFilter>>>>,
+    error: RefCell<Option<anyhow::Error>>,
+}
+
+impl<'a> NestedPathFilter<'a> {
+    pub fn new(root: &Path, root_filter: Box<dyn PathFilter + 'a>) -> NestedPathFilter<'a> {
+        NestedPathFilter {
+            root: root.to_path_buf(),
+            root_filter,
+            dir_filters: RefCell::new(HashMap::new()),
+            error: RefCell::new(None),
+        }
+    }
+
+    fn dir_filter(&self, dir: &Path) -> Option<Rc<dyn PathFilter>> {
+        if let Some(filter) = self.dir_filters.borrow().get(dir) {
+            return filter.clone();
+        }
+
+        let filter = match load_dir_filter(dir) {
+            Ok(filter) => filter.map(Rc::from),
+            Err(err) => {
+                self.error.borrow_mut().get_or_insert(err);
+                None
+            }
+        };
+        self.dir_filters.borrow_mut().insert(dir.to_path_buf(), filter.clone());
+        filter
+    }
+
+    /// Returns the filters applying to the given path, starting with the root filter.
+    fn filters(&self, p: &Path) -> Vec<Rc<dyn PathFilter>> {
+        let mut filters = Vec::new();
+        if let Some(parent) = p.parent().and_then(|d| d.strip_prefix(&self.root).ok()) {
+            let mut dir = self.root.clone();
+            for component in parent.components() {
+                dir.push(component);
+                filters.extend(self.dir_filter(&dir));
+            }
+        }
+        filters
+    }
+
+    fn evaluate(&self, p: &Path, matches: &dyn Fn(&dyn PathFilter) -> bool) -> bool {
+        if !matches(self.root_filter.as_ref()) {
+            return false;
+        }
+
+        let parent = match p.parent().and_then(|d| d.strip_prefix(&self.root).ok()) {
+            Some(parent) => parent,
+            None => return true
+        };
+
+        let mut dir = self.root.clone();
+        for component in parent.components() {
+            dir.push(component);
+            if let Some(filter) = self.dir_filter(&dir) {
+                if !matches(filter.as_ref()) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl PathFilter for NestedPathFilter<'_> {
+    fn matches(&self, p: &Path) -> bool {
+        self.evaluate(p, &|f| f.matches(p))
+    }
+
+    fn matches_with_metadata(&self, p: &Path, metadata: &Metadata) -> bool {
+        self.evaluate(p, &|f| f.matches_with_metadata(p, metadata))
+    }
+
+    fn matches_path(&self, p: &Path) -> bool {
+        self.evaluate(p, &|f| f.matches_path(p))
+    }
+
+    fn explain(&self, p: &Path) -> Option<RuleMatch> {
+        let dir_filters = self.filters(p);
+        let filters = std::iter::once(self.root_filter.as_ref()).
+            chain(dir_filters.iter().map(|f| f.as_ref()));
+        explain_all(filters, p)
+    }
+
+    fn check(&self) -> Result<()> {
+        self.root_filter.check()?;
+        match self.error.borrow_mut().take() {
+            Some(err) => Err(err),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::filter::globfilter::GLOB_FILTER_FILENAME;
+    use crate::filter::load_filter;
+
+    use super::*;
+
+    #[test]
+    fn test_matches_nested_ignore_files() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::create_dir_all(temp.path().join("a/b"))?;
+        fs::write(temp.path().join(GLOB_FILTER_FILENAME), "*.log\n")?;
+        fs::write(temp.path().join("a").join(GLOB_FILTER_FILENAME), "*.tmp\nb/x.txt\n")?;
+
+        // When
+        let filter = load_filter(temp.path())?;
+
+        // Then
+        assert!(!filter.matches(&temp.path().join("a.log")));
+        assert!(filter.matches(&temp.path().join("a.tmp")));
+        assert!(filter.matches(&temp.path().join("b/x.txt")));
+        assert!(!filter.matches(&temp.path().join("a/c.tmp")));
+        assert!(!filter.matches(&temp.path().join("a/b/x.txt")));
+        assert!(filter.matches(&temp.path().join("a/b/y.txt")));
+        assert!(filter.matches(&temp.path().join("a").join(GLOB_FILTER_FILENAME)));
+        assert!(filter.check().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_nested_gitignore() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::create_dir_all(temp.path().join("a/b"))?;
+        fs::write(temp.path().join("a").join(GLOB_FILTER_FILENAME), "# auditr: gitignore\n*.txt\n!keep.txt\n")?;
+
+        // When
+        let filter = load_filter(temp.path())?;
+
+        // Then
+        assert!(filter.matches(&temp.path().join("x.txt")));
+        assert!(!filter.matches(&temp.path().join("a/b/x.txt")));
+        assert!(filter.matches(&temp.path().join("a/b/keep.txt")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_invalid_nested_ignore_file() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::create_dir_all(temp.path().join("a"))?;
+        fs::write(temp.path().join("a").join(GLOB_FILTER_FILENAME), "[\n")?;
+
+        // When
+        let filter = load_filter(temp.path())?;
+
+        // Then
+        assert!(filter.matches(&temp.path().join("a/x.txt")));
+        assert!(filter.check().is_err());
+
+        Ok(())
+    }
+}
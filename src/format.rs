@@ -0,0 +1,340 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use crate::entry;
+use crate::entry::Entry;
+use crate::report;
+use crate::stats::Stats;
+
+/// Output format of an audit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Human readable output.
+    #[default]
+    Text,
+    /// JUnit XML, each file is a test case and each change a failure.
+    Junit,
+    /// SARIF 2.1.0, each change is a result.
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "junit" => Ok(Format::Junit),
+            "sarif" => Ok(Format::Sarif),
+            _ => bail!("invalid format '{}', expected text, junit or sarif", s)
+        }
+    }
+}
+
+const RULES: [(&str, &str); 9] = [
+    ("added", "File was added"),
+    ("copied", "File was added as a copy of a file in the index"),
+    ("updated", "File was updated"),
+    ("bitrot", "File content changed but modification timestamp did not (bitrot)"),
+    ("removed", "File was removed"),
+    ("moved", "File was moved"),
+    ("moved-modified", "File was moved and modified"),
+    ("renamed", "File was renamed to another Unicode normalization form"),
+    ("skipped", "File was skipped by a metadata rule and not verified"),
+];
+
+/// A change of a single file.
+#[derive(Debug, PartialEq)]
+struct Finding {
+    rule: &'static str,
+    path: String,
+    message: String,
+}
+
+fn findings(stats: &Stats, skipped: &[Entry]) -> Vec<Finding> {
+    let finding = |rule, path: String, message: String| Finding { rule, path, message };
+    let mut findings = Vec::new();
+
+    for e in stats.added.iter() {
+        findings.push(finding("added", e.to_string(), String::from("File was added")));
+    }
+    for (copy, original) in stats.copied.iter() {
+        findings.push(finding("copied", copy.to_string(), format!("File was added as a copy of {}", original)));
+    }
+    for e in stats.updated.iter() {
+        findings.push(finding("updated", e.to_string(), String::from("File was updated")));
+    }
+    for e in stats.updated_bitrot.iter() {
+        findings.push(finding("bitrot", e.to_string(),
+                              String::from("File content changed but modification timestamp did not (bitrot)")));
+    }
+    for e in stats.removed.iter() {
+        findings.push(finding("removed", e.to_string(), String::from("File was removed")));
+    }
+    for (old, new) in stats.moved.iter() {
+        findings.push(finding("moved", new.to_string(), format!("File was moved from {}", old.to_string_lossy())));
+    }
+    for (old, new, similarity) in stats.moved_modified.iter() {
+        findings.push(finding("moved-modified", new.to_string(),
+                              format!("File was moved from {} and modified ({}% similar)", old, similarity)));
+    }
+    for (old, new) in stats.renamed.iter() {
+        findings.push(finding("renamed", new.to_string(), format!("File was renamed from {} to {}",
+                                                                  entry::normalization_form(old),
+                                                                  entry::normalization_form(&new.path))));
+    }
+    for e in skipped.iter() {
+        findings.push(finding("skipped", e.to_string(), String::from("File was skipped by a metadata rule and not verified")));
+    }
+
+    findings.sort_by(|a, b| a.path.cmp(&b.path).then_with(|| a.rule.cmp(b.rule)));
+    findings
+}
+
+/// Creates a JUnit XML report of the given stats. Every file is a test case, which fails if the
+/// file was changed and is skipped if a metadata rule skipped it.
+pub fn junit(root: &Path, stats: &Stats, skipped: &[Entry]) -> String {
+    let findings = findings(stats, skipped);
+    let mut cases = stats.unchanged.iter().
+        map(|e| (e.to_string(), None)).
+        chain(findings.iter().map(|f| (f.path.clone(), Some(f)))).
+        collect::<Vec<(String, Option<&Finding>)>>();
+    cases.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let tests = cases.len();
+    let failures = findings.len() - skipped.len();
+    let skipped = skipped.len();
+    let name = escape(&root.to_string_lossy());
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(out, "<testsuites name=\"auditr\" tests=\"{}\" failures=\"{}\">", tests, failures);
+    let _ = writeln!(out, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\">",
+                     name, tests, failures, skipped);
+    for (path, finding) in cases {
+        let path = escape(&path);
+        match finding {
+            None => {
+                let _ = writeln!(out, "    <testcase classname=\"{}\" name=\"{}\"/>", name, path);
+            }
+            Some(f) => {
+                let _ = writeln!(out, "    <testcase classname=\"{}\" name=\"{}\">", name, path);
+                if f.rule == "skipped" {
+                    let _ = writeln!(out, "      <skipped message=\"{}\"/>", escape(&f.message));
+                } else {
+                    let _ = writeln!(out, "      <failure type=\"{}\" message=\"{}\"/>", f.rule, escape(&f.message));
+                }
+                out.push_str("    </testcase>\n");
+            }
+        }
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+/// Creates a SARIF 2.1.0 log of the given stats with a result for every changed file. Bitrot is
+/// reported as error, files skipped by metadata rules as notes and all other changes as warnings.
+pub fn sarif(root: &Path, stats: &Stats, skipped: &[Entry]) -> Result<String> {
+    let rules = RULES.iter().
+        map(|(id, description)| json!({
+            "id": id,
+            "shortDescription": { "text": description },
+        })).
+        collect::<Vec<Value>>();
+
+    let results = findings(stats, skipped).iter().
+        map(|f| json!({
+            "ruleId": f.rule,
+            "ruleIndex": RULES.iter().position(|(id, _)| *id == f.rule),
+            "level": match f.rule {
+                "bitrot" => "error",
+                "skipped" => "note",
+                _ => "warning"
+            },
+            "message": { "text": f.message },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": {
+                        "uri": encode_uri(&f.path),
+                        "uriBaseId": "ROOT",
+                    }
+                }
+            }],
+        })).
+        collect::<Vec<Value>>();
+
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let log = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "auditr",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "originalUriBaseIds": {
+                "ROOT": { "uri": format!("file://{}/", encode_uri(&root.to_string_lossy())) }
+            },
+            "results": results,
+        }]
+    });
+    Ok(serde_json::to_string_pretty(&log)?)
+}
+
+/// Escapes the given text for XML attributes. Characters which are not allowed in XML 1.0, like
+/// most control characters, are replaced by U+FFFD; tabs and line breaks are kept as character
+/// references.
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in report::escape(s).chars() {
+        match c {
+            '\t' | '\n' | '\r' => {
+                let _ = write!(out, "&#{};", c as u32);
+            }
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => out.push('\u{fffd}'),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encodes all characters of the given path which are not allowed in URIs.
+fn encode_uri(path: &str) -> String {
+    let mut out = String::new();
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(b as char),
+            _ => {
+                let _ = write!(out, "%{:02X}", b);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::FromIterator;
+
+    use crate::diff::Event;
+    use crate::entry::given_entry;
+
+    use super::*;
+
+    #[test]
+    fn test_junit() {
+        // Given
+        let unchanged = given_entry("a.txt", "h1", 100, 123);
+        let old = given_entry("b&c.txt", "h2", 100, 123);
+        let rotten = given_entry("b&c.txt", "h3", 100, 123);
+        let stats = Stats::from_iter(vec![
+            Event::UNCHANGED { old: &unchanged, new: &unchanged },
+            Event::UPDATED { old: &old, new: &rotten },
+        ]);
+
+        // When
+        let xml = junit(Path::new("/data"), &stats, &[]);
+
+        // Then
+        assert_eq!(xml, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuites name=\"auditr\" tests=\"2\" failures=\"1\">
+  <testsuite name=\"/data\" tests=\"2\" failures=\"1\" errors=\"0\" skipped=\"0\">
+    <testcase classname=\"/data\" name=\"a.txt\"/>
+    <testcase classname=\"/data\" name=\"b&amp;c.txt\">
+      <failure type=\"bitrot\" message=\"File content changed but modification timestamp did not (bitrot)\"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+");
+    }
+
+    #[test]
+    fn test_junit_skipped() {
+        // Given
+        let unchanged = given_entry("a.txt", "h1", 100, 123);
+        let skipped = given_entry("b.txt", "h2", 100, 123);
+        let stats = Stats::from_iter(vec![
+            Event::UNCHANGED { old: &unchanged, new: &unchanged },
+        ]);
+
+        // When
+        let xml = junit(Path::new("/data"), &stats, &[skipped]);
+
+        // Then
+        assert!(xml.contains("<testsuite name=\"/data\" tests=\"2\" failures=\"0\" errors=\"0\" skipped=\"1\">"));
+        assert!(xml.contains("    <testcase classname=\"/data\" name=\"b.txt\">
+      <skipped message=\"File was skipped by a metadata rule and not verified\"/>
+    </testcase>"));
+    }
+
+    #[test]
+    fn test_junit_control_characters() {
+        // Given
+        let unchanged = given_entry("a\u{1}b\tc.txt", "h1", 100, 123);
+        let stats = Stats::from_iter(vec![
+            Event::UNCHANGED { old: &unchanged, new: &unchanged },
+        ]);
+
+        // When
+        let xml = junit(Path::new("/data"), &stats, &[]);
+
+        // Then
+        assert!(xml.contains("<testcase classname=\"/data\" name=\"a\u{fffd}b&#9;c.txt\"/>"));
+    }
+
+    #[test]
+    fn test_sarif() -> Result<()> {
+        // Given
+        let old = given_entry("a/file.txt", "h1", 100, 123);
+        let moved = given_entry("b/my file.txt", "h1", 100, 123);
+        let stats = Stats::from_iter(vec![
+            Event::REMOVED { old: &old },
+            Event::ADDED { new: &moved },
+        ]);
+
+        // When
+        let log: Value = serde_json::from_str(&sarif(Path::new("/data"), &stats, &[])?)?;
+
+        // Then
+        let run = &log["runs"][0];
+        assert_eq!(run["originalUriBaseIds"]["ROOT"]["uri"], "file:///data/");
+        assert_eq!(run["results"], json!([{
+            "ruleId": "moved",
+            "ruleIndex": 5,
+            "level": "warning",
+            "message": { "text": "File was moved from a/file.txt" },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": "b/my%20file.txt", "uriBaseId": "ROOT" }
+                }
+            }],
+        }]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sarif_skipped() -> Result<()> {
+        // Given
+        let skipped = given_entry("a.txt", "h1", 100, 123);
+        let stats = Stats::from_iter(Vec::<Event<Entry>>::new());
+
+        // When
+        let log: Value = serde_json::from_str(&sarif(Path::new("/data"), &stats, &[skipped])?)?;
+
+        // Then
+        let result = &log["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "skipped");
+        assert_eq!(result["ruleIndex"], 8);
+        assert_eq!(result["level"], "note");
+
+        Ok(())
+    }
+}
@@ -1,3 +1,6 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::{BufReader, BufWriter, Write};
@@ -5,12 +8,19 @@ use std::io::BufRead;
 use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, bail, Result};
+use unicode_normalization::UnicodeNormalization;
 
 use crate::entry::Entry;
 use crate::filter::PathFilter;
+use crate::merkle;
 
 pub const HASH_INDEX_FILENAME: &str = ".auditr-sha256";
 pub const META_INDEX_FILENAME: &str = ".auditr-meta";
+pub const DIR_INDEX_FILENAME: &str = ".auditr-dirs";
+
+/// First line of meta indexes storing the paths as found on disk. Older indexes store the paths
+/// normalized to NFC.
+const RAW_PATHS_HEADER: &str = "# paths: raw";
 
 pub fn index_exists(path: &Path) -> bool {
     let hash_index_file = path.join(HASH_INDEX_FILENAME);
@@ -21,13 +31,43 @@ pub fn index_exists(path: &Path) -> bool {
 pub fn load(path: &Path, filter: &dyn PathFilter) -> Result<Vec<Entry>> {
     let hash_index = read_hash_index(path, filter)?;
     let meta_index = read_meta_index(path, filter)?;
-    join_indices(hash_index, meta_index)
+    let mut entries = join_indices(hash_index, meta_index)?;
+
+    if !has_raw_paths(path)? {
+        resolve_legacy_paths(path, &mut entries);
+    }
+    Ok(entries)
 }
 
-pub fn save(path: &Path, entries: &[Entry]) -> Result<()> {
+/// Saves the index and returns the root hash of the directory tree.
+pub fn save(path: &Path, entries: &[Entry]) -> Result<String> {
     write_hash_index(&path.join(HASH_INDEX_FILENAME), &entries)?;
     write_meta_index(&path.join(META_INDEX_FILENAME), &entries)?;
-    Ok(())
+
+    let digests = merkle::directory_digests(entries);
+    write_dir_index(&path.join(DIR_INDEX_FILENAME), &digests)?;
+    Ok(digests[merkle::ROOT_DIR].clone())
+}
+
+/// Loads the directory digests stored in the index, or returns `None` for indexes written before
+/// directory digests were stored.
+pub fn load_digests(path: &Path) -> Result<Option<BTreeMap<String, String>>> {
+    let file_name = path.join(DIR_INDEX_FILENAME);
+    if !file_name.exists() {
+        return Ok(None);
+    }
+
+    let reader = BufReader::new(File::open(file_name)?);
+    let digests = reader.lines().
+        map(|line| {
+            let line = line?;
+            match line.split_once("  ") {
+                Some((digest, dir)) => Ok((dir.to_owned(), digest.to_owned())),
+                None => bail!("invalid directory index")
+            }
+        }).
+        collect::<Result<BTreeMap<String, String>>>()?;
+    Ok(Some(digests))
 }
 
 fn read_hash_index(path: &Path, filter: &dyn PathFilter) -> Result<Vec<Entry>> {
@@ -38,11 +78,8 @@ fn read_hash_index(path: &Path, filter: &dyn PathFilter) -> Result<Vec<Entry>> {
         }
 
         Ok(Entry {
-            path: PathBuf::from(line[1]),
-            norm_path: line[1].to_string(),
             hash: String::from(line[0]),
-            len: 0,
-            modified: 0,
+            ..Entry::from_path(Path::new(line[1]))
         })
     })
 }
@@ -55,13 +92,11 @@ fn read_meta_index(path: &Path, filter: &dyn PathFilter) -> Result<Vec<Entry>> {
         }
 
         Ok(Entry {
-            path: PathBuf::from(line[2]),
-            norm_path: line[2].to_string(),
-            hash: String::new(),
             len: line[1].parse::<u64>().
                 map_err(|err| anyhow!("invalid meta format: invalid length: {}", err))?,
             modified: line[0].parse::<u64>().
                 map_err(|err| anyhow!("invalid meta format: invalid modified timestamp: {}", err))?,
+            ..Entry::from_path(Path::new(line[2]))
         })
     })
 }
@@ -72,14 +107,16 @@ fn read_index<F>(path: &Path, file_name: &str, filter: &dyn PathFilter, mut f: F
     let reader = BufReader::new(file);
 
     let mut entries: Result<Vec<Entry>> = reader.lines().
+        filter(|line| !line.as_ref().is_ok_and(|l| l.starts_with('#'))).
         map(|line| f(line?)).
         filter(|entry| {
             if let Ok(e) = entry {
-                filter.matches(&path.join(e.path.as_path()))
+                filter.matches_path(&path.join(e.path.as_path()))
             } else {
                 true
             }
         }).collect();
+    filter.check()?;
 
     if let Ok(ref mut e) = entries {
         e.sort_unstable()
@@ -88,6 +125,56 @@ fn read_index<F>(path: &Path, file_name: &str, filter: &dyn PathFilter, mut f: F
     entries
 }
 
+fn has_raw_paths(path: &Path) -> Result<bool> {
+    let file = File::open(path.join(META_INDEX_FILENAME))?;
+    let first_line = BufReader::new(file).lines().next().transpose()?;
+    Ok(first_line.as_deref() == Some(RAW_PATHS_HEADER))
+}
+
+/// Replaces the NFC paths of an index written before raw paths were stored by the paths found on
+/// disk, which may be in another normalization form (e.g. NFD on HFS+). Otherwise, all files with
+/// non-ASCII names would be reported as renamed. Paths which are not found are kept.
+fn resolve_legacy_paths(root: &Path, entries: &mut [Entry]) {
+    let mut listings = HashMap::new();
+    for e in entries.iter_mut() {
+        if e.path.to_str().is_some_and(|p| p.is_ascii()) {
+            continue;
+        }
+
+        if let Some(resolved) = resolve_legacy_path(root, &e.path, &mut listings) {
+            *e = Entry {
+                hash: std::mem::take(&mut e.hash),
+                len: e.len,
+                modified: e.modified,
+                ..Entry::from_path(&resolved)
+            };
+        }
+    }
+    entries.sort_unstable();
+}
+
+fn resolve_legacy_path(root: &Path, path: &Path, listings: &mut HashMap<PathBuf, HashMap<String, OsString>>) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        let name = component.as_os_str();
+        if root.join(&resolved).join(name).symlink_metadata().is_ok() {
+            resolved.push(name);
+            continue;
+        }
+
+        // Names in the directory listing by their NFC form
+        let listing = listings.entry(resolved.clone()).or_insert_with_key(|dir| {
+            fs::read_dir(root.join(dir)).into_iter().
+                flatten().
+                flatten().
+                map(|e| (e.file_name().to_string_lossy().nfc().collect(), e.file_name())).
+                collect()
+        });
+        resolved.push(listing.get(name.to_string_lossy().as_ref())?);
+    }
+    Some(resolved)
+}
+
 fn join_indices(hash_index: Vec<Entry>, meta_index: Vec<Entry>) -> Result<Vec<Entry>> {
     if hash_index.len() != meta_index.len() {
         bail!("indices must have same number of entries");
@@ -114,7 +201,7 @@ fn write_hash_index(file_name: &Path, entries: &[Entry]) -> io::Result<()> {
     let result = File::create(file_name)?;
     let mut writer = BufWriter::new(result);
     for t in entries {
-        writeln!(writer, "{}  {}", t.hash, t)?;
+        writeln!(writer, "{}  {}", t.hash, t.path.to_string_lossy())?;
     }
     Ok(())
 }
@@ -122,20 +209,33 @@ fn write_hash_index(file_name: &Path, entries: &[Entry]) -> io::Result<()> {
 fn write_meta_index(file_name: &Path, entries: &[Entry]) -> io::Result<()> {
     let result = File::create(file_name)?;
     let mut writer = BufWriter::new(result);
+    writeln!(writer, "{}", RAW_PATHS_HEADER)?;
     for t in entries {
-        writeln!(writer, "{}  {}  {}", t.modified, t.len, t)?;
+        writeln!(writer, "{}  {}  {}", t.modified, t.len, t.path.to_string_lossy())?;
+    }
+    Ok(())
+}
+
+fn write_dir_index(file_name: &Path, digests: &BTreeMap<String, String>) -> io::Result<()> {
+    let result = File::create(file_name)?;
+    let mut writer = BufWriter::new(result);
+    for (dir, digest) in digests {
+        writeln!(writer, "{}  {}", digest, dir)?;
     }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
     use std::fs;
+    use std::path::PathBuf;
 
     use indoc::indoc;
     use tempfile::tempdir;
 
     use crate::filter::DefaultPathFilter;
+    use crate::filter::globfilter::{GlobPathFilter, GlobRule};
 
     use super::*;
 
@@ -212,6 +312,61 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_load_legacy_index() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::create_dir(temp.path().join("cafe\u{301}"))?;
+        fs::write(temp.path().join("cafe\u{301}/re\u{301}sume\u{301}.txt"), "a")?;
+
+        // Index written before raw paths were stored, with NFC paths
+        fs::write(temp.path().join(HASH_INDEX_FILENAME), indoc!("
+            ca978112ca1bbdcafac231b39a23dc4da786eff7814c8c9e1a43fcbc4e3cbbc5  caf\u{e9}/r\u{e9}sum\u{e9}.txt
+            3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d  caf\u{e9}/removed.txt
+            "))?;
+        fs::write(temp.path().join(META_INDEX_FILENAME), indoc!("
+            1  1  caf\u{e9}/r\u{e9}sum\u{e9}.txt
+            2  1  caf\u{e9}/removed.txt
+            "))?;
+
+        // When
+        let entries = load(temp.path(), &DefaultPathFilter::new(temp.path()))?;
+
+        // Then
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, PathBuf::from("caf\u{e9}/removed.txt"));
+        assert_eq!(entries[1].path, PathBuf::from("cafe\u{301}/re\u{301}sume\u{301}.txt"));
+        assert_eq!(entries[1].norm_path, "caf\u{e9}/r\u{e9}sum\u{e9}.txt");
+        assert_eq!(entries[1].hash, "ca978112ca1bbdcafac231b39a23dc4da786eff7814c8c9e1a43fcbc4e3cbbc5");
+        assert_eq!(entries[1].modified, 1);
+
+        // Indexes with raw paths are taken as they are
+        save(temp.path(), &entries[1..])?;
+        let entries = load(temp.path(), &DefaultPathFilter::new(temp.path()))?;
+        assert_eq!(entries[0].path, PathBuf::from("cafe\u{301}/re\u{301}sume\u{301}.txt"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_ignores_meta_rules() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::write(temp.path().join("large.bin"), vec![0; 2048])?;
+        fs::write(temp.path().join(HASH_INDEX_FILENAME), "0675e5e9efc82e1a795e61b616093adb13b6140b0f658d1f71ec8b9b733418fb  large.bin\n")?;
+        fs::write(temp.path().join(META_INDEX_FILENAME), "1771134938456  2048  large.bin\n")?;
+        let filter = GlobPathFilter::new(temp.path(), vec![GlobRule::try_from("size>1K")?], true)?;
+
+        // When
+        let entries = load(temp.path(), &filter)?;
+
+        // Then
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path.to_string_lossy(), "large.bin");
+
+        Ok(())
+    }
+
     #[test]
     fn test_load_not_matching_files() -> Result<()> {
         // Given
@@ -349,7 +504,7 @@ mod tests {
         ];
 
         // When
-        save(temp.path(), &entries)?;
+        let root_hash = save(temp.path(), &entries)?;
 
         // Then
         let expected_hash_index_content = indoc!("
@@ -360,12 +515,38 @@ mod tests {
         assert_eq!(result, expected_hash_index_content);
 
         let expected_meta_index_content = indoc!("
+            # paths: raw
             1578770227005  297742332  test/a.txt
             1225221568000  46738654  test/b.txt
             ");
         let result = fs::read_to_string(temp.path().join(META_INDEX_FILENAME))?;
         assert_eq!(result, expected_meta_index_content);
 
+        let result = fs::read_to_string(temp.path().join(DIR_INDEX_FILENAME))?;
+        let digests = merkle::directory_digests(&entries);
+        assert_eq!(result, format!("{}  .\n{}  test\n", digests["."], digests["test"]));
+        assert_eq!(root_hash, digests["."]);
+        assert_eq!(load_digests(temp.path())?, Some(digests));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_digests() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+
+        // When / Then
+        assert_eq!(load_digests(temp.path())?, None);
+
+        fs::write(temp.path().join(DIR_INDEX_FILENAME), "abc  .\ndef  a/b c\n")?;
+        let digests = load_digests(temp.path())?.unwrap();
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests["a/b c"], "def");
+
+        fs::write(temp.path().join(DIR_INDEX_FILENAME), "INVALID\n")?;
+        assert_eq!(load_digests(temp.path()).map_err(|e| e.to_string()), Err(String::from("invalid directory index")));
+
         Ok(())
     }
 
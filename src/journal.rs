@@ -0,0 +1,363 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::entry::to_hex;
+use crate::stats::Stats;
+
+pub const JOURNAL_FILENAME: &str = ".auditr-journal";
+
+/// Hash referenced by the first record of the journal.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Number of files per change category of a single run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Counts {
+    pub added: u64,
+    pub copied: u64,
+    pub removed: u64,
+    pub updated: u64,
+    pub bitrot: u64,
+    pub moved: u64,
+    pub moved_modified: u64,
+    pub renamed: u64,
+    pub unchanged: u64,
+    pub total: u64,
+}
+
+impl From<&Stats<'_>> for Counts {
+    fn from(stats: &Stats) -> Self {
+        Counts {
+            added: stats.added.len() as u64,
+            copied: stats.copied.len() as u64,
+            removed: stats.removed.len() as u64,
+            updated: stats.updated.len() as u64,
+            bitrot: stats.updated_bitrot.len() as u64,
+            moved: stats.moved.len() as u64,
+            moved_modified: stats.moved_modified.len() as u64,
+            renamed: stats.renamed.len() as u64,
+            unchanged: stats.unchanged.len() as u64,
+            total: stats.total,
+        }
+    }
+}
+
+/// A single journal record. Each record contains the hash of its predecessor, so that modifying or
+/// removing a record breaks the chain of all following records.
+///
+/// If a key is given, the hash is an HMAC-SHA256 of the record. Otherwise, it is a plain SHA-256,
+/// which only detects accidental damage: anyone can rewrite the journal and compute new hashes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub command: String,
+    pub exit_code: i32,
+    pub root_hash: String,
+    pub counts: Counts,
+    pub prev: String,
+    /// Whether the hash is an HMAC.
+    pub keyed: bool,
+    pub hash: String,
+}
+
+impl Record {
+    fn body(&self) -> String {
+        let c = &self.counts;
+        format!("seq={} time={} command={} exit={} root={} added={} copied={} removed={} updated={} \
+                 bitrot={} moved={} moved_modified={} renamed={} unchanged={} total={} prev={} auth={}",
+                self.seq, self.timestamp, self.command, self.exit_code, self.root_hash, c.added, c.copied,
+                c.removed, c.updated, c.bitrot, c.moved, c.moved_modified, c.renamed, c.unchanged, c.total,
+                self.prev, if self.keyed { "hmac" } else { "none" })
+    }
+
+    fn compute_hash(&self, key: Option<&[u8]>) -> Result<String> {
+        match key {
+            Some(key) => {
+                let mut mac = HmacSha256::new_varkey(key).
+                    map_err(|_| anyhow!("invalid key length"))?;
+                mac.input(self.body().as_bytes());
+                Ok(to_hex(&mac.result().code()))
+            }
+            None => Ok(to_hex(&Sha256::digest(self.body().as_bytes())))
+        }
+    }
+
+    fn parse(line: &str) -> Result<Record> {
+        let mut fields = line.split(' ').map(|f| {
+            let mut kv = f.splitn(2, '=');
+            (kv.next().unwrap_or_default(), kv.next())
+        });
+
+        let mut next = |key: &str| -> Result<&str> {
+            match fields.next() {
+                Some((k, Some(v))) if k == key => Ok(v),
+                _ => Err(anyhow!("missing field '{}'", key))
+            }
+        };
+
+        let record = Record {
+            seq: next("seq")?.parse()?,
+            timestamp: next("time")?.parse()?,
+            command: next("command")?.to_owned(),
+            exit_code: next("exit")?.parse()?,
+            root_hash: next("root")?.to_owned(),
+            counts: Counts {
+                added: next("added")?.parse()?,
+                copied: next("copied")?.parse()?,
+                removed: next("removed")?.parse()?,
+                updated: next("updated")?.parse()?,
+                bitrot: next("bitrot")?.parse()?,
+                moved: next("moved")?.parse()?,
+                moved_modified: next("moved_modified")?.parse()?,
+                renamed: next("renamed")?.parse()?,
+                unchanged: next("unchanged")?.parse()?,
+                total: next("total")?.parse()?,
+            },
+            prev: next("prev")?.to_owned(),
+            keyed: match next("auth")? {
+                "hmac" => true,
+                "none" => false,
+                a => bail!("invalid authentication '{}'", a)
+            },
+            hash: next("hash")?.to_owned(),
+        };
+        if fields.next().is_some() {
+            bail!("unexpected trailing fields");
+        }
+        Ok(record)
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hash={}", self.body(), self.hash)
+    }
+}
+
+/// Loads all records of the journal of the given directory. Returns an empty list if no journal
+/// exists yet.
+pub fn load(root: &Path) -> Result<Vec<Record>> {
+    let path = root.join(JOURNAL_FILENAME);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(&path)?;
+    contents.lines().
+        enumerate().
+        map(|(i, line)| Record::parse(line).
+            with_context(|| format!("Invalid journal entry in line {}", i + 1))).
+        collect()
+}
+
+/// Appends a new record to the journal of the given directory. The record is authenticated with an
+/// HMAC if a key is given.
+pub fn append(root: &Path, command: &str, root_hash: &str, counts: Counts, exit_code: i32, key: Option<&[u8]>) -> Result<Record> {
+    let last = load(root)?.pop();
+    let mut record = Record {
+        seq: last.as_ref().map_or(1, |r| r.seq + 1),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        command: command.to_owned(),
+        exit_code,
+        root_hash: root_hash.to_owned(),
+        counts,
+        prev: last.map_or_else(|| GENESIS_HASH.to_owned(), |r| r.hash),
+        keyed: key.is_some(),
+        hash: String::new(),
+    };
+    record.hash = record.compute_hash(key)?;
+
+    let mut file = OpenOptions::new().create(true).append(true).open(root.join(JOURNAL_FILENAME))?;
+    writeln!(file, "{}", record)?;
+    Ok(record)
+}
+
+/// Verifies the hash chain of the given journal records. If `head` is given, it must be the hash of
+/// one of the records; this detects records removed from the end of the journal. Returns a
+/// description of the first inconsistency found, if any.
+///
+/// If a key is given, every record must be authenticated with it, so that a rewritten or truncated
+/// journal is detected. Without a key, the HMAC of authenticated records cannot be checked, and
+/// only their position in the chain is verified.
+pub fn verify(records: &[Record], head: Option<&str>, key: Option<&[u8]>) -> Result<Option<String>> {
+    let mut prev = GENESIS_HASH;
+    for (i, r) in records.iter().enumerate() {
+        let seq = i as u64 + 1;
+        if r.seq != seq || r.prev != prev {
+            return Ok(Some(format!("journal entry {} is missing", seq)));
+        }
+        if key.is_some() && !r.keyed {
+            return Ok(Some(format!("journal entry {} is not authenticated with the key", seq)));
+        }
+        if r.keyed == key.is_some() && r.hash != r.compute_hash(key)? {
+            return Ok(Some(format!("journal entry {} has been modified", seq)));
+        }
+        prev = &r.hash;
+    }
+
+    match head {
+        Some(head) if !records.iter().any(|r| r.hash == head) =>
+            Ok(Some(format!("journal does not contain the entry with hash {}", head))),
+        _ => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const KEY: &[u8] = b"secret";
+
+    fn given_journal(root: &Path, n: u64) -> Result<Vec<Record>> {
+        given_journal_with_key(root, n, None)
+    }
+
+    fn given_journal_with_key(root: &Path, n: u64, key: Option<&[u8]>) -> Result<Vec<Record>> {
+        for i in 0..n {
+            let counts = Counts {
+                unchanged: i,
+                total: i,
+                ..Counts::default()
+            };
+            append(root, "audit", "abc", counts, 0, key)?;
+        }
+        load(root)
+    }
+
+    #[test]
+    fn test_append_load() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+
+        // When
+        let records = given_journal(root.path(), 3)?;
+
+        // Then
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].seq, 1);
+        assert_eq!(records[0].prev, GENESIS_HASH);
+        assert_eq!(records[1].prev, records[0].hash);
+        assert_eq!(records[2].prev, records[1].hash);
+        assert_eq!(records[2].counts.total, 2);
+        assert_eq!(verify(&records, None, None)?, None);
+        assert_eq!(verify(&records, Some(&records[1].hash), None)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_modified() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let mut records = given_journal(root.path(), 3)?;
+
+        // When
+        records[1].exit_code = 2;
+
+        // Then
+        assert_eq!(verify(&records, None, None)?, Some(String::from("journal entry 2 has been modified")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_removed() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let mut records = given_journal(root.path(), 3)?;
+
+        // When
+        records.remove(1);
+
+        // Then
+        assert_eq!(verify(&records, None, None)?, Some(String::from("journal entry 2 is missing")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_removed_head() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let mut records = given_journal(root.path(), 3)?;
+        let head = records[2].hash.clone();
+
+        // When
+        records.pop();
+
+        // Then
+        assert_eq!(verify(&records, None, None)?, None);
+        assert_eq!(verify(&records, Some(&head), None)?,
+                   Some(format!("journal does not contain the entry with hash {}", head)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_keyed() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let records = given_journal_with_key(root.path(), 3, Some(KEY))?;
+
+        // Then
+        assert!(records.iter().all(|r| r.keyed));
+        assert_eq!(verify(&records, None, Some(KEY))?, None);
+        assert_eq!(verify(&records, None, Some(&b"other"[..]))?, Some(String::from("journal entry 1 has been modified")));
+        assert_eq!(verify(&records, None, None)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_keyed_rewritten() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let mut records = given_journal_with_key(root.path(), 3, Some(KEY))?;
+
+        // When: the journal is truncated and the remaining records are rehashed without the key
+        records.pop();
+        let mut prev = GENESIS_HASH.to_owned();
+        for r in records.iter_mut() {
+            r.prev = prev;
+            r.keyed = false;
+            r.hash = r.compute_hash(None)?;
+            prev = r.hash.clone();
+        }
+
+        // Then
+        assert_eq!(verify(&records, None, None)?, None);
+        assert_eq!(verify(&records, None, Some(KEY))?, Some(String::from("journal entry 1 is not authenticated with the key")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_invalid() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        given_journal(root.path(), 1)?;
+        let mut file = OpenOptions::new().append(true).open(root.path().join(JOURNAL_FILENAME))?;
+        writeln!(file, "seq=2 time=now")?;
+
+        // When
+        let result = load(root.path());
+
+        // Then
+        assert_eq!(result.map_err(|e| e.to_string()), Err(String::from("Invalid journal entry in line 2")));
+
+        Ok(())
+    }
+}
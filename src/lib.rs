@@ -1,15 +1,21 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
 use std::io::{self, IsTerminal};
-use std::io::{BufRead, stdout};
-use std::path::Path;
+use std::io::{BufRead, stderr};
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use colored::Colorize;
 use pbr::{ProgressBar, Units};
 
 use crate::diff::diff_iter;
-use crate::entry::Entry;
-use crate::filter::DefaultPathFilter;
+use crate::entry::{Entry, Normalization};
+use crate::filter::{DefaultPathFilter, FilterOptions, Origin, PathFilter, RuleMatch};
+use crate::format::Format;
+use crate::journal::Counts;
+use crate::repair::Mirror;
 use crate::stats::Stats;
+use crate::summary::{DirSummary, SortOrder};
 
 pub mod entry;
 pub mod diff;
@@ -17,42 +23,111 @@ pub mod stats;
 pub mod index;
 pub mod analyze;
 pub mod filter;
+pub mod repair;
+pub mod parity;
+pub mod blocks;
+pub mod chunks;
+pub mod merkle;
+pub mod signature;
+pub mod journal;
+pub mod dupes;
+pub mod summary;
+pub mod report;
+pub mod format;
+pub mod config;
+
+/// Options for the data recorded alongside the index by `init` and `update`.
+#[derive(Clone, Debug, Default)]
+pub struct IndexOptions {
+    /// Overhead (in percent) of the Reed-Solomon parity data to generate.
+    pub parity: Option<u32>,
+    /// Record hashes of fixed-size blocks.
+    pub block_hashes: bool,
+    /// Record fingerprints of content-defined chunks.
+    pub chunk_fingerprints: bool,
+}
+
+/// Options for showing the differences found by `update` and `audit`.
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    /// Show all moved files, also those collapsed into moved directories.
+    pub verbose: bool,
+    /// Show a summary of the changes per directory up to the given depth.
+    pub summary_depth: Option<usize>,
+    pub summary_order: SortOrder,
+    /// Write an HTML report of the audit to the given file.
+    pub report: Option<PathBuf>,
+    pub format: Format,
+}
 
-pub fn init(directory: &str) -> Result<i32> {
+/// Options of a directory tree compared by `compare`, resolved from the configuration of the tree.
+#[derive(Clone, Debug, Default)]
+pub struct TreeOptions {
+    /// Verify the index with the secret key in the given file.
+    pub key_file: Option<String>,
+    /// Filter used to compute the hashes if the tree has no index.
+    pub filter: FilterOptions,
+    pub normalization: Normalization,
+}
+
+pub fn init(directory: &str, index_options: &IndexOptions, key_file: Option<&str>, filter_options: &FilterOptions, normalization: Normalization) -> Result<i32> {
     let path = Path::new(directory);
+    let key = load_key(key_file)?;
     if index::index_exists(path) {
         bail!("An index already exists in this directory!");
     }
 
-    let filter = filter::load_filter(path)?;
+    let filter = filter::load_filter_with(path, filter_options)?;
     let total = analyze::total_file_size(path, filter.as_ref())?;
     let pb_update = init_progress(total);
 
-    let entries = analyze::analyze_dir(path, filter.as_ref(), true, true, pb_update)?;
+    let mut entries = analyze::analyze_dir(path, filter.as_ref(), true, true, pb_update)?;
+    entry::normalize(&mut entries, normalization);
+    show_collisions(&entries);
+
+    let root_hash = save_index(path, &entries, key.as_deref())?;
+    update_index_data(path, &entries, index_options);
 
-    index::save(path, &entries)?;
+    let counts = Counts {
+        added: entries.len() as u64,
+        total: entries.len() as u64,
+        ..Counts::default()
+    };
+    append_journal(path, "init", &root_hash, counts, 0, key.as_deref());
 
     println!("{}", "Successfully initialized.".bold().green());
+    print_root_hash(&root_hash);
 
     Ok(0)
 }
 
-pub fn update(directory: &str) -> Result<i32> {
+pub fn update(directory: &str, index_options: &IndexOptions, key_file: Option<&str>, filter_options: &FilterOptions, normalization: Normalization, output_options: &OutputOptions) -> Result<i32> {
     let path = Path::new(directory);
-    let entries = index::load(path, &DefaultPathFilter::new(path)).
-        with_context(|| format!("No index found in directory '{}'", directory))?;
-
-    let filter = filter::load_filter(path)?;
-    let actual = analyze::analyze_dir(path, filter.as_ref(), true, false, |_| {})?;
+    let key = load_key(key_file)?;
+    let mut entries = load_index(path, key.as_deref())?;
+    entry::normalize(&mut entries, normalization);
+
+    let filter = filter::load_filter_with(path, filter_options)?;
+    let mut actual = analyze::analyze_dir(path, filter.as_ref(), true, false, |_| {})?;
+    entry::normalize(&mut actual, normalization);
+    let skipped = skipped_entries(path, filter.as_ref(), &entries, &actual);
+    entries.retain(|e| skipped.binary_search(e).is_err());
+    show_collisions(&actual);
     let it = diff_iter(entries.iter(), actual.iter(), Entry::compare_meta);
 
     let stats: Stats = it.collect();
     if !stats.modified() {
+        let entries = with_skipped(&entries, &skipped);
+        update_index_data(path, &entries, index_options);
+        let root_hash = merkle::root_hash(&entries);
+        append_journal(path, "update", &root_hash, Counts::from(&stats), 0, key.as_deref());
+        show_skipped(&skipped);
         println!("{}", "Nothing to update.".bold().green());
+        print_root_hash(&root_hash);
         return Ok(0);
     }
 
-    show_stats(&stats);
+    show_stats(&stats, &skipped, &entries, output_options);
 
     if !confirm("Continue? [N/y]")? {
         println!("{}", "Aborted.".bold().yellow());
@@ -73,53 +148,546 @@ pub fn update(directory: &str) -> Result<i32> {
     let mut updated_entries = stats.iter_new().
         map(with_hash).
         collect::<Result<Vec<Entry>>>()?;
+    updated_entries.extend(skipped.iter().cloned());
     updated_entries.sort_unstable();
 
-    index::save(path, &updated_entries)?;
+    let root_hash = save_index(path, &updated_entries, key.as_deref())?;
+    update_index_data(path, &updated_entries, index_options);
+    append_journal(path, "update", &root_hash, Counts::from(&stats), 0, key.as_deref());
+
+    print_root_hash(&root_hash);
     Ok(0)
 }
 
-pub fn audit(directory: &str, update: bool) -> Result<i32> {
+pub fn audit(directory: &str, update: bool, similarity: Option<u32>, key_file: Option<&str>, filter_options: &FilterOptions, normalization: Normalization, output_options: &OutputOptions) -> Result<i32> {
     let path = Path::new(directory);
-    let entries = index::load(path, &DefaultPathFilter::new(path))?;
+    let key = load_key(key_file)?;
+    let mut entries = load_index(path, key.as_deref())?;
+    entry::normalize(&mut entries, normalization);
 
-    let filter = filter::load_filter(path)?;
+    let filter = filter::load_filter_with(path, filter_options)?;
     let total = analyze::total_file_size(path, filter.as_ref())?;
-    let pb_update = init_progress(total);
+    let text = output_options.format == Format::Text;
+    let pb_update = init_progress(if text { total } else { 0 });
 
-    let actual = analyze::analyze_dir(path, filter.as_ref(), true, true, pb_update)?;
+    let mut actual = analyze::analyze_dir(path, filter.as_ref(), true, true, pb_update)?;
+    entry::normalize(&mut actual, normalization);
+    let skipped = skipped_entries(path, filter.as_ref(), &entries, &actual);
+    entries.retain(|e| skipped.binary_search(e).is_err());
+    show_collisions(&actual);
 
     let it = diff_iter(entries.iter(), actual.iter(), Entry::compare_hash_and_mtime);
 
-    let stats: Stats = it.collect();
+    let mut stats: Stats = it.collect();
+    if let Some(threshold) = similarity {
+        let pairs = chunks::similar_pairs(path, &stats.removed, &stats.added, threshold)?;
+        stats.add_moved_modified(pairs);
+    }
+
+    match output_options.format {
+        Format::Text => show_stats(&stats, &skipped, &entries, output_options),
+        Format::Junit => print!("{}", format::junit(path, &stats, &skipped)),
+        Format::Sarif => println!("{}", format::sarif(path, &stats, &skipped)?),
+    }
+
+    if let Some(report) = &output_options.report {
+        fs::write(report, report::html(path, &stats, &entries)).
+            with_context(|| format!("Failed to write report '{}'", report.display()))?;
+        if text {
+            println!("Report written to {}.", report.display());
+        }
+    }
 
-    show_stats(&stats);
+    let indexed = with_skipped(&actual, &skipped);
+    let exit_code = if !stats.updated_bitrot.is_empty() {
+        if text {
+            show_corrupted_ranges(path, &entries, &stats)?;
+
+            println!("{}", "Audit failed - bitrot detected!".bold().red());
+
+            if update {
+                println!("Index was not updated.")
+            }
+        }
 
-    if !stats.updated_bitrot.is_empty() {
-        println!("{}", "Audit failed - bitrot detected!".bold().red());
+        3
+    } else if stats.modified() {
+        if text {
+            println!("{}", "Audit failed - difference detected!".bold().red());
+        }
 
         if update {
-            println!("Index was not updated.")
+            let root_hash = save_index(path, &indexed, key.as_deref())?;
+            update_index_data(path, &indexed, &IndexOptions::default());
+            if text {
+                println!("Index updated.");
+                print_root_hash(&root_hash);
+            }
+        }
+
+        2
+    } else if !skipped.is_empty() {
+        if text {
+            let line = format!("Audit incomplete - {} files were skipped and not verified!", skipped.len());
+            println!("{}", line.bold().yellow());
+        }
+        4
+    } else {
+        if text {
+            println!("{}", "Audit successful.".bold().green());
+        }
+        0
+    };
+
+    append_journal(path, "audit", &merkle::root_hash(&indexed), Counts::from(&stats), exit_code, key.as_deref());
+    Ok(exit_code)
+}
+
+pub fn repair(directory: &str, mirror: Option<&str>, use_parity: bool, key_file: Option<&str>, filter_options: &FilterOptions, normalization: Normalization) -> Result<i32> {
+    if mirror.is_none() && !use_parity {
+        bail!("Either a mirror directory or parity data must be used for repair!");
+    }
+
+    let path = Path::new(directory);
+    let key = load_key(key_file)?;
+    let mut entries = load_index(path, key.as_deref())?;
+    entry::normalize(&mut entries, normalization);
+
+    let filter = filter::load_filter_with(path, filter_options)?;
+    let total = analyze::total_file_size(path, filter.as_ref())?;
+    let pb_update = init_progress(total);
+
+    let mut actual = analyze::analyze_dir(path, filter.as_ref(), true, true, pb_update)?;
+    entry::normalize(&mut actual, normalization);
+
+    let it = diff_iter(entries.iter(), actual.iter(), Entry::compare_hash_and_mtime);
+
+    let stats: Stats = it.collect();
+    if stats.updated_bitrot.is_empty() {
+        println!("{}", "Nothing to repair.".bold().green());
+        return Ok(0);
+    }
+
+    let mut mirror = mirror.map(|m| Mirror::new(Path::new(m)));
+    let mut repaired = 0;
+    let mut unrecoverable = 0;
+
+    for &rotten in stats.updated_bitrot.iter() {
+        let expected = indexed_entry(&entries, rotten)?;
+
+        // A file which cannot be read or reconstructed does not prevent repairing the others
+        match repair_file(path, expected, use_parity, mirror.as_mut()) {
+            Ok(Some(source)) => {
+                let line = format!("[{}] {} (from {})", "R", rotten, source);
+                println!("{}", line.green());
+                repaired += 1;
+            }
+            Ok(None) => {
+                println!("{}", format!("[{}] {}", "!", rotten).red());
+                unrecoverable += 1;
+            }
+            Err(err) => {
+                println!("{}", format!("[{}] {} ({:#})", "!", rotten, err).red());
+                unrecoverable += 1;
+            }
         }
+    }
+
+    println!();
+    println!("{}", "====================================".dimmed());
+    print_stat("Repaired:", repaired);
+    print_stat("Unrecoverable:", unrecoverable);
+    println!("{}", "====================================".dimmed());
+    println!();
 
+    if unrecoverable > 0 {
+        println!("{}", "Repair failed - some files could not be recovered!".bold().red());
         return Ok(3);
     }
 
+    println!("{}", "Repair successful.".bold().green());
+    Ok(0)
+}
+
+/// Restores the expected content of a file from parity data or from the mirror. Returns a
+/// description of the source, or `None` if the file could not be restored.
+fn repair_file(path: &Path, expected: &Entry, use_parity: bool, mirror: Option<&mut Mirror>) -> Result<Option<String>> {
+    // Corrupted parity data is no reason not to try the mirror
+    let mut parity_err = None;
+    if use_parity {
+        match parity::repair(path, expected) {
+            Ok(true) => return Ok(Some(String::from("parity data"))),
+            Ok(false) => {}
+            Err(err) => parity_err = Some(err),
+        }
+    }
+
+    if let Some(mirror) = mirror {
+        if let Some(source) = mirror.find(expected)? {
+            if repair::restore(path, &mirror.root().join(&source), expected)? {
+                return Ok(Some(source.to_string_lossy().into_owned()));
+            }
+        }
+    }
+
+    parity_err.map_or(Ok(None), Err)
+}
+
+pub fn sign(directory: &str, key_file: &str) -> Result<i32> {
+    let path = Path::new(directory);
+    if !index::index_exists(path) {
+        bail!("No index found in directory '{}'", directory);
+    }
+
+    let key = signature::load_key(Path::new(key_file))?;
+    signature::sign(path, &key)?;
+
+    println!("{}", "Index signed.".bold().green());
+    Ok(0)
+}
+
+pub fn compare(primary: &str, backup: &str, primary_options: &TreeOptions, backup_options: &TreeOptions) -> Result<i32> {
+    if primary_options.normalization != backup_options.normalization {
+        bail!("Primary and backup are configured with different normalization forms, use --normalization to compare them!");
+    }
+
+    let primary_entries = load_or_analyze(Path::new(primary), primary_options)?;
+    let backup_entries = load_or_analyze(Path::new(backup), backup_options)?;
+
+    let primary_digests = load_digests(Path::new(primary), &primary_entries)?;
+    let backup_digests = load_digests(Path::new(backup), &backup_entries)?;
+    println!("Primary root hash: {}", primary_digests[merkle::ROOT_DIR]);
+    println!("Backup root hash: {}", backup_digests[merkle::ROOT_DIR]);
+
+    // Only files in directories with diverging digests need to be compared
+    let diverging = merkle::diverging_dirs(&primary_digests, &backup_digests);
+    let (primary_entries, identical): (Vec<&Entry>, Vec<&Entry>) = primary_entries.iter().
+        partition(|e| diverging.contains(&merkle::parent_dir(e)));
+    let backup_entries = backup_entries.iter().
+        filter(|e| diverging.contains(&merkle::parent_dir(e))).
+        collect::<Vec<&Entry>>();
+
+    let it = diff_iter(primary_entries.into_iter(), backup_entries.into_iter(), Entry::compare_hash);
+
+    let mut stats: Stats = it.collect();
+    stats.total += identical.len() as u64;
+    stats.unchanged.extend(identical);
+
+    show_comparison(&stats);
+
     if stats.modified() {
-        println!("{}", "Audit failed - difference detected!".bold().red());
+        println!("{}", "Comparison failed - backup is not a faithful replica!".bold().red());
+        return Ok(2);
+    }
 
-        if update {
-            index::save(path, &actual)?;
-            println!("Index updated.");
+    println!("{}", "Comparison successful - backup is a faithful replica.".bold().green());
+    Ok(0)
+}
+
+pub fn dupes(directory: &str, verify: bool, json: bool, key_file: Option<&str>, filter_options: &FilterOptions, normalization: Normalization) -> Result<i32> {
+    let path = Path::new(directory);
+    let key = load_key(key_file)?;
+    let mut entries = load_index(path, key.as_deref())?;
+    entry::normalize(&mut entries, normalization);
+
+    let filter = filter::load_filter_with(path, filter_options)?;
+    entries.retain(|e| is_included(path, filter.as_ref(), &e.path));
+
+    let verified;
+    let mut missing = Vec::new();
+    let mut groups = dupes::find(&entries);
+    if verify {
+        let total = groups.iter().flatten().fold(0, |c, e| c + e.len);
+        let pb_update = init_progress(if json { 0 } else { total });
+        (verified, missing) = dupes::rehash(path, &groups, pb_update)?;
+        groups = dupes::find(&verified);
+    }
+
+    let report = dupes::report(&groups, &missing);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(0);
+    }
+
+    for set in report.sets.iter() {
+        println!("{}", format!("[=] {} files of {} bytes ({} bytes wasted, hash {})",
+                               set.paths.len(), set.size, set.wasted, set.hash).yellow());
+        for p in set.paths.iter() {
+            println!("    {}", p);
         }
+    }
+    for e in missing.iter() {
+        println!("{}", format!("[{}] {} (missing)", "-", e).yellow());
+    }
+
+    println!();
+    println!("{}", "====================================".dimmed());
+    print_stat("Duplicate sets:", report.sets.len());
+    print_stat("Duplicate files:", report.files as usize);
+    print_stat("Wasted bytes:", report.wasted as usize);
+    print_stat("Missing:", report.missing.len());
+    println!("{}", "====================================".dimmed());
+    println!();
 
+    if report.sets.is_empty() {
+        println!("{}", "No duplicates found.".bold().green());
+    }
+    Ok(0)
+}
+
+pub fn config_show(directory: &str) -> Result<i32> {
+    print!("{}", config::describe(Path::new(directory))?);
+    Ok(0)
+}
+
+pub fn journal_verify(directory: &str, head: Option<&str>, key_file: Option<&str>) -> Result<i32> {
+    let path = Path::new(directory);
+    let key = load_key(key_file)?;
+    let records = journal::load(path)?;
+    if records.is_empty() {
+        bail!("No journal found in directory '{}'", directory);
+    }
+
+    if key.is_none() && records.iter().any(|r| r.keyed) {
+        eprintln!("{}", "Warning: the journal is authenticated, but no key was given to verify it!".bold().yellow());
+    }
+
+    if let Some(err) = journal::verify(&records, head, key.as_deref())? {
+        println!("{}", format!("Journal verification failed - {}!", err).bold().red());
         return Ok(2);
     }
 
-    println!("{}", "Audit successful.".bold().green());
+    println!("{}", format!("Journal verified - {} entries.", records.len()).bold().green());
+    println!("Head hash: {}", records[records.len() - 1].hash);
+    Ok(0)
+}
+
+pub fn check_ignore(directory: &str, paths: &[String], filter_options: &FilterOptions) -> Result<i32> {
+    let root = Path::new(directory);
+    let filter = filter::load_filter_with(root, filter_options)?;
+
+    for p in paths {
+        let path = root.join(p);
+        let rel_path = path.strip_prefix(root).ok().
+            filter(|r| !r.as_os_str().is_empty()).
+            ok_or_else(|| anyhow!("Path '{}' is not inside directory '{}'", p, directory))?;
+
+        // Files in an excluded directory are never visited, whatever the rules for the file are
+        let excluded_dir = rel_path.ancestors().skip(1).
+            filter(|d| !d.as_os_str().is_empty()).
+            collect::<Vec<&Path>>().into_iter().rev().
+            find(|d| !filter.matches(&root.join(d)));
+
+        let line = match excluded_dir {
+            Some(dir) => match filter.explain(&root.join(dir)) {
+                Some(m) => format!("excluded by {} (via {})", describe_rule(root, &m), dir.display()),
+                None => format!("excluded (via {})", dir.display())
+            },
+            None => match (filter.matches(&path), filter.explain(&path)) {
+                (true, Some(m)) if m.include => format!("included by {}", describe_rule(root, &m)),
+                (false, Some(m)) if !m.include => format!("excluded by {}", describe_rule(root, &m)),
+                (true, _) => String::from("included (default)"),
+                (false, _) => String::from("excluded")
+            }
+        };
+        println!("{}: {}", rel_path.display(), line);
+    }
+    filter.check()?;
+
+    Ok(0)
+}
+
+/// Describes a rule by its origin and text. Ignore files are shown relative to the root directory.
+fn describe_rule(root: &Path, m: &RuleMatch) -> String {
+    match &m.origin {
+        Origin::File(file_name, line) => {
+            let file_name = file_name.strip_prefix(root).unwrap_or(file_name);
+            format!("{}:{} '{}'", file_name.display(), line, m.rule)
+        }
+        origin => format!("{} '{}'", origin, m.rule)
+    }
+}
+
+pub fn ls_files(directory: &str, filter_options: &FilterOptions) -> Result<i32> {
+    let path = Path::new(directory);
+    let filter = filter::load_filter_with(path, filter_options)?;
+    let entries = analyze::analyze_dir(path, filter.as_ref(), false, false, |_| ())?;
+
+    for entry in &entries {
+        println!("{}", entry.path.display());
+    }
     Ok(0)
 }
 
+/// Loads the index of the given directory as `load_index` does, or computes the hashes of all
+/// files if there is no index.
+fn load_or_analyze(path: &Path, options: &TreeOptions) -> Result<Vec<Entry>> {
+    let key = load_key(options.key_file.as_deref())?;
+    let mut entries = if index::index_exists(path) {
+        load_index(path, key.as_deref())?
+    } else {
+        eprintln!("No index found in directory '{}', computing hashes.", path.display());
+
+        let filter = filter::load_filter_with(path, &options.filter)?;
+        let total = analyze::total_file_size(path, filter.as_ref())?;
+        let pb_update = init_progress(total);
+
+        analyze::analyze_dir(path, filter.as_ref(), true, true, pb_update)?
+    };
+
+    entry::normalize(&mut entries, options.normalization);
+    show_collisions(&entries);
+    Ok(entries)
+}
+
+/// Returns the directory digests of the given entries. If the index of the directory stores
+/// digests, they must match the ones computed from its entries.
+fn load_digests(path: &Path, entries: &[Entry]) -> Result<BTreeMap<String, String>> {
+    let digests = merkle::directory_digests(entries);
+    if index::index_exists(path) {
+        if let Some(stored) = index::load_digests(path)? {
+            if stored != digests {
+                bail!("Directory digests in '{}' do not match the index - the index may have been tampered with!", path.display());
+            }
+        }
+    }
+    Ok(digests)
+}
+
+fn load_key(key_file: Option<&str>) -> Result<Option<Vec<u8>>> {
+    key_file.map(|k| signature::load_key(Path::new(k))).transpose()
+}
+
+/// Loads the index of the given directory. If a key is given, the index must have a valid
+/// signature. Otherwise, a warning is shown for signed indexes.
+fn load_index(path: &Path, key: Option<&[u8]>) -> Result<Vec<Entry>> {
+    let entries = index::load(path, &DefaultPathFilter::new(path)).
+        with_context(|| format!("No index found in directory '{}'", path.display()))?;
+
+    match key {
+        Some(key) if !signature::verify(path, key)? => {
+            bail!("Index signature is missing or invalid - the index may have been tampered with!");
+        }
+        None if signature::is_signed(path) => {
+            eprintln!("{}", "Warning: the index is signed, but no key was given to verify it!".bold().yellow());
+        }
+        _ => {}
+    }
+
+    Ok(entries)
+}
+
+/// Saves the index of the given directory and signs it if a key is given. The signature of a
+/// signed index is removed if no key is given, because it would not match anymore.
+fn save_index(path: &Path, entries: &[Entry], key: Option<&[u8]>) -> Result<String> {
+    let root_hash = index::save(path, entries)?;
+
+    match key {
+        Some(key) => signature::sign(path, key)?,
+        None if signature::is_signed(path) => {
+            fs::remove_file(path.join(signature::SIGNATURE_FILENAME))?;
+            eprintln!("{}", "Warning: the index signature was removed because no key was given!".bold().yellow());
+        }
+        None => {}
+    }
+
+    Ok(root_hash)
+}
+
+/// Synchronizes the parity data, block hashes and chunk fingerprints with the given index entries.
+/// Files which cannot be read or whose contents do not match the indexed hash are skipped with a
+/// warning, since data derived from them would preserve the corruption. Failures never fail the
+/// command, because the index has already been saved at this point.
+fn update_index_data(path: &Path, entries: &[Entry], index_options: &IndexOptions) {
+    let results = [
+        parity::update(path, entries, index_options.parity),
+        blocks::update(path, entries, index_options.block_hashes),
+        chunks::update(path, entries, index_options.chunk_fingerprints),
+    ];
+
+    let mut failed = Vec::new();
+    for result in results {
+        match result {
+            Ok(f) => failed.extend(f),
+            Err(err) => {
+                let line = format!("Warning: failed to update parity data, block hashes or fingerprints: {:#}", err);
+                eprintln!("{}", line.bold().yellow());
+            }
+        }
+    }
+    failed.sort_by(|a, b| a.0.cmp(b.0));
+    failed.dedup_by(|a, b| a.0 == b.0);
+
+    for (e, err) in failed {
+        let line = format!("Warning: no parity data, block hashes or fingerprints were recorded for '{}': {:#}", e, err);
+        eprintln!("{}", line.bold().yellow());
+    }
+}
+
+/// Returns the index entries of files which still exist, but were skipped by metadata rules while
+/// walking the directory tree (e.g. `mtime<10m` for files still being written). These files are
+/// not verified, so they are reported as skipped instead of as removed.
+fn skipped_entries(root: &Path, filter: &dyn PathFilter, entries: &[Entry], actual: &[Entry]) -> Vec<Entry> {
+    entries.iter().
+        filter(|e| actual.binary_search(e).is_err()).
+        filter(|e| is_included(root, filter, &e.path) && root.join(&e.path).is_file()).
+        cloned().
+        collect()
+}
+
+/// Adds the index entries of files skipped by metadata rules to the given entries. Skipped files
+/// keep their indexed hash unverified, so that bitrot can still be detected once they are walked
+/// again.
+fn with_skipped(entries: &[Entry], skipped: &[Entry]) -> Vec<Entry> {
+    let mut entries = entries.iter().
+        chain(skipped.iter()).
+        cloned().
+        collect::<Vec<Entry>>();
+    entries.sort_unstable();
+    entries
+}
+
+/// Returns whether the given relative path and all its parent directories pass the path rules of
+/// the filter.
+fn is_included(root: &Path, filter: &dyn PathFilter, path: &Path) -> bool {
+    path.ancestors().
+        filter(|p| !p.as_os_str().is_empty()).
+        all(|p| filter.matches_path(&root.join(p)))
+}
+
+/// Appends a record to the journal. The result of the command does not depend on the journal, so a
+/// journal which cannot be written only causes a warning.
+fn append_journal(path: &Path, command: &str, root_hash: &str, counts: Counts, exit_code: i32, key: Option<&[u8]>) {
+    if let Err(err) = journal::append(path, command, root_hash, counts, exit_code, key) {
+        eprintln!("{}", format!("Warning: failed to append to the journal: {:#}", err).bold().yellow());
+    }
+}
+
+fn indexed_entry<'a>(entries: &'a [Entry], entry: &Entry) -> Result<&'a Entry> {
+    entries.binary_search(entry).
+        map(|i| &entries[i]).
+        map_err(|_| anyhow!("Entry '{}' not found in index", entry))
+}
+
+fn show_corrupted_ranges(path: &Path, entries: &[Entry], stats: &Stats) -> Result<()> {
+    let mut shown = false;
+    for &rotten in stats.updated_bitrot.iter() {
+        let expected = indexed_entry(entries, rotten)?;
+        if let Some(ranges) = blocks::corrupted_ranges(path, expected)? {
+            let ranges = ranges.iter().
+                map(|(start, end)| format!("{}-{}", start, end - 1)).
+                collect::<Vec<String>>();
+            println!("Corrupted bytes of {}: {}", rotten, ranges.join(", "));
+            shown = true;
+        }
+    }
+
+    if shown {
+        println!();
+    }
+    Ok(())
+}
+
 fn confirm(msg: &str) -> Result<bool> {
     println!("{}", msg);
 
@@ -130,11 +698,22 @@ fn confirm(msg: &str) -> Result<bool> {
     Ok(str.eq_ignore_ascii_case("y\n"))
 }
 
-fn show_stats(stats: &Stats) {
+fn show_skipped(skipped: &[Entry]) {
+    for s in skipped.iter() {
+        let line = format!("[{}] {} (skipped by a metadata rule, not verified)", "?", s);
+        println!("{}", line.yellow());
+    }
+}
+
+fn show_stats(stats: &Stats, skipped: &[Entry], entries: &[Entry], output_options: &OutputOptions) {
     if stats.modified() {
         for s in stats.added.iter() {
             print_file("+", s);
         }
+        for (s, original) in stats.copied.iter() {
+            let line = format!("[{}] {} (copy of {})", "=", s, original);
+            println!("{}", line.yellow());
+        }
         for s in stats.updated.iter() {
             print_file("*", s);
         }
@@ -144,25 +723,118 @@ fn show_stats(stats: &Stats) {
         for s in stats.removed.iter() {
             print_file("-", s);
         }
-        for (k, s) in stats.moved.iter() {
-            let line = format!("[{}] {} (from {})", ">", s, k.to_string_lossy());
+        show_moved(stats, output_options.verbose);
+        for (old, new, similarity) in stats.moved_modified.iter() {
+            let line = format!("[{}] {} (from {}, {}% similar)", "%", new, old, similarity);
             println!("{}", line.yellow());
         }
+        show_renamed(stats);
+
+        if let Some(depth) = output_options.summary_depth {
+            show_summary(&summary::summarize(stats, entries, depth, output_options.summary_order));
+        }
     }
+    show_skipped(skipped);
 
     println!();
     println!("{}", "====================================".dimmed());
     print_stat("New:", stats.added.len());
+    print_stat("Copied:", stats.copied.len());
+    print_stat("Copied (bytes):", stats.copied_bytes() as usize);
     print_stat("Updated:", stats.updated.len());
     print_stat("Updated (bitrot):", stats.updated_bitrot.len());
     print_stat("Removed:", stats.removed.len());
     print_stat("Moved:", stats.moved.len());
+    print_stat("Moved+modified:", stats.moved_modified.len());
+    print_stat("Renamed (NFC/NFD):", stats.renamed.len());
+    print_stat("Skipped:", skipped.len());
     print_stat("Unchanged:", stats.unchanged.len());
     print_stat("Total:", stats.total as usize);
     println!("{}", "====================================".dimmed());
     println!();
 }
 
+fn show_summary(summary: &[DirSummary]) {
+    println!();
+    println!("{:40}{:>8}{:>8}{:>8}{:>8}{:>16}", "Directory".bold(), "+", "-", "*", ">", "Bytes");
+    for s in summary {
+        println!("{:40}{:>8}{:>8}{:>8}{:>8}{:>16}", s.dir.to_string_lossy(),
+                 s.added, s.removed, s.updated, s.moved, format!("{:+}", s.bytes));
+    }
+}
+
+fn show_comparison(stats: &Stats) {
+    if stats.modified() {
+        for s in stats.removed.iter() {
+            print_file("-", s);
+        }
+        for s in stats.updated.iter().chain(stats.updated_bitrot.iter()) {
+            print_file("*", s);
+        }
+        for s in stats.added.iter().chain(stats.copied.iter().map(|(copy, _)| copy)) {
+            print_file("+", s);
+        }
+        for (k, s) in stats.moved.iter() {
+            let line = format!("[{}] {} (from {})", ">", s, k.to_string_lossy());
+            println!("{}", line.yellow());
+        }
+        show_renamed(stats);
+    }
+
+    println!();
+    println!("{}", "====================================".dimmed());
+    print_stat("Missing in backup:", stats.removed.len());
+    print_stat("Content differs:", stats.updated.len() + stats.updated_bitrot.len());
+    print_stat("Only in backup:", stats.added.len() + stats.copied.len());
+    print_stat("Moved:", stats.moved.len());
+    print_stat("Renamed (NFC/NFD):", stats.renamed.len());
+    print_stat("Identical:", stats.unchanged.len());
+    println!("{}", "====================================".dimmed());
+    println!();
+}
+
+/// Shows moved directories and the moved files outside of them. With `verbose`, all moved files
+/// are shown in addition.
+fn show_moved(stats: &Stats, verbose: bool) {
+    let moved_dirs = stats.moved_dirs();
+    for d in moved_dirs.iter() {
+        let line = format!("[{}] {}/ (directory moved from {}/, {} files)", ">",
+                           d.to.to_string_lossy(), d.from.to_string_lossy(), d.files.len());
+        println!("{}", line.yellow());
+    }
+
+    let collapsed = moved_dirs.iter().
+        flat_map(|d| d.files.iter()).
+        filter(|_| !verbose).
+        collect::<HashSet<&PathBuf>>();
+    for (k, s) in stats.moved.iter().filter(|(k, _)| !collapsed.contains(k)) {
+        let line = format!("[{}] {} (from {})", ">", s, k.to_string_lossy());
+        println!("{}", line.yellow());
+    }
+}
+
+fn show_renamed(stats: &Stats) {
+    for (k, s) in stats.renamed.iter() {
+        let line = format!("[{}] {} (renamed from {} to {})", "~", s,
+                           entry::normalization_form(k), entry::normalization_form(&s.path));
+        println!("{}", line.yellow());
+    }
+}
+
+/// Warns about files whose paths are equal after normalization. Such files cannot be copied to
+/// file systems which normalize file names.
+fn show_collisions(entries: &[Entry]) {
+    for (a, b) in entry::collisions(entries) {
+        let line = format!("Warning: '{}' exists in different normalization forms ({} and {})!", a,
+                           entry::normalization_form(&a.path), entry::normalization_form(&b.path));
+        eprintln!("{}", line.bold().yellow());
+    }
+}
+
+fn print_root_hash(root_hash: &str) {
+    println!("Root hash: {}", root_hash);
+}
+
 fn print_file(event: &str, entry: &Entry) {
     println!("{}", format!("[{}] {}", event, entry).yellow());
 }
@@ -173,18 +845,20 @@ fn print_stat(name: &str, count: usize) {
     }
 }
 
+/// Shows the progress on stderr, so that the output on stdout can be processed further. No
+/// progress is shown if stderr is not a terminal or the total is zero.
 fn init_progress(total: u64) -> impl FnMut(u64) -> u64 {
-    let out = stdout();
-    let is_a_tty = out.is_terminal();
-
-    let mut pb = ProgressBar::on(out, total);
-    pb.set_units(Units::Bytes);
+    let out = stderr();
+    let mut pb = if total > 0 && out.is_terminal() {
+        let mut pb = ProgressBar::on(out, total);
+        pb.set_units(Units::Bytes);
+        Some(pb)
+    } else {
+        None
+    };
 
-    move |c| {
-        if is_a_tty {
-            pb.add(c)
-        } else {
-            0
-        }
+    move |c| match pb.as_mut() {
+        Some(pb) => pb.add(c),
+        None => 0
     }
 }
\ No newline at end of file
@@ -1,10 +1,14 @@
+use std::path::{Path, PathBuf};
 use std::process;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use colored::Colorize;
 
 use auditr::*;
+use auditr::config::Config;
+use auditr::entry::Normalization;
+use auditr::filter::FilterOptions;
 
 /// Auditr collects hashes and file system metadata of all files in a directory tree.
 /// The collected data can be used at later point in time to detect changes (like files added, removed, or updated).
@@ -20,7 +24,19 @@ enum SubCommand {
     /// Creates the directory index initially
     #[command(name = "init")]
     Init {
-        directory: String
+        directory: String,
+
+        #[command(flatten)]
+        index: IndexArgs,
+
+        #[command(flatten)]
+        key: KeyArgs,
+
+        #[command(flatten)]
+        normalization: NormalizationArgs,
+
+        #[command(flatten)]
+        filter: FilterArgs,
     },
 
     /// Updates the directory index
@@ -29,6 +45,21 @@ enum SubCommand {
     #[command(name = "update")]
     Update {
         directory: String,
+
+        #[command(flatten)]
+        index: IndexArgs,
+
+        #[command(flatten)]
+        key: KeyArgs,
+
+        #[command(flatten)]
+        normalization: NormalizationArgs,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Compares the directory's current state to the index and outputs the differences
@@ -39,16 +70,374 @@ enum SubCommand {
         /// Update the index after audit unless bitrot was detected.
         #[arg(short, long)]
         update: bool,
+
+        /// Pair removed and added files whose contents are at least the given percentage similar as
+        /// moved and modified. Requires chunk fingerprints recorded with --chunks.
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..=100))]
+        similarity: Option<u32>,
+
+        /// Write a self-contained HTML report of the audit to the given file.
+        #[arg(long, value_name = "FILE")]
+        report: Option<String>,
+
+        /// Output format: text (default), junit (JUnit XML, each file is a test case and changes
+        /// are failures), or sarif (SARIF 2.1.0).
+        #[arg(long, value_name = "FORMAT", value_parser = ["text", "junit", "sarif"])]
+        format: Option<String>,
+
+        #[command(flatten)]
+        key: KeyArgs,
+
+        #[command(flatten)]
+        normalization: NormalizationArgs,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Repairs files with bitrot by reconstructing them from parity data or
+    /// by restoring a copy with matching hash from a mirror directory
+    #[command(name = "repair")]
+    Repair {
+        directory: String,
+
+        /// Root directory of the mirror copy to restore files from.
+        #[arg(long = "from")]
+        from: Option<String>,
+
+        /// Reconstruct files from the parity data stored alongside the index.
+        #[arg(long)]
+        parity: bool,
+
+        #[command(flatten)]
+        key: KeyArgs,
+
+        #[command(flatten)]
+        normalization: NormalizationArgs,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    /// Signs the existing index with the secret key in the given file
+    #[command(name = "sign")]
+    Sign {
+        directory: String,
+
+        /// File containing the secret key.
+        #[arg(long, value_name = "FILE")]
+        key: String,
+    },
+
+    /// Compares a directory tree with a backup copy and checks whether the backup is a faithful replica.
+    /// Uses the index of each directory if present, otherwise the hashes are computed
+    #[command(name = "compare")]
+    Compare {
+        primary: String,
+
+        backup: String,
+
+        #[command(flatten)]
+        key: KeyArgs,
+
+        /// Verify the index of the backup with the secret key in the given file. Defaults to the
+        /// key configured for the backup.
+        #[arg(long, value_name = "FILE")]
+        backup_key: Option<String>,
+
+        #[command(flatten)]
+        normalization: NormalizationArgs,
+    },
+
+    /// Shows which ignore rule includes or excludes the given paths
+    #[command(name = "check-ignore")]
+    CheckIgnore {
+        directory: String,
+
+        /// Paths relative to the directory.
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    /// Lists the files which would be indexed, without computing hashes
+    #[command(name = "ls-files")]
+    LsFiles {
+        directory: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    /// Reports files with identical contents according to the index
+    #[command(name = "dupes")]
+    Dupes {
+        directory: String,
+
+        /// Compute the hashes of duplicate candidates again instead of trusting the index.
+        #[arg(long)]
+        verify: bool,
+
+        /// Print the report as JSON.
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        key: KeyArgs,
+
+        #[command(flatten)]
+        normalization: NormalizationArgs,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    /// Inspects the journal of all init, update and audit runs of a directory
+    #[command(name = "journal")]
+    Journal {
+        #[command(subcommand)]
+        subcmd: JournalCommand,
+    },
+
+    /// Shows the configuration
+    #[command(name = "config")]
+    Config {
+        #[command(subcommand)]
+        subcmd: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum JournalCommand {
+    /// Verifies that no journal entry has been modified or removed
+    #[command(name = "verify")]
+    Verify {
+        directory: String,
+
+        /// Hash of a previously verified journal entry, which must still be part of the journal.
+        #[arg(long, value_name = "HASH")]
+        head: Option<String>,
+
+        #[command(flatten)]
+        key: KeyArgs,
     },
 }
 
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Shows the effective configuration of a directory and the file each value is taken from
+    #[command(name = "show")]
+    Show {
+        directory: String,
+    },
+}
+
+#[derive(Args)]
+struct KeyArgs {
+    /// Sign and verify the index and the journal with the secret key in the given file.
+    #[arg(long, value_name = "FILE")]
+    key: Option<String>,
+}
+
+#[derive(Args)]
+struct NormalizationArgs {
+    /// Unicode normalization form used to compare paths (nfc (default), nfd, or none for
+    /// byte-exact paths).
+    #[arg(long, value_name = "FORM", value_parser = ["nfc", "nfd", "none"])]
+    normalization: Option<String>,
+}
+
+#[derive(Args, Default)]
+struct FilterArgs {
+    /// Honor .gitignore files and skip version control and build directories like .git/ or target/.
+    #[arg(long, overrides_with = "no_respect_gitignore")]
+    respect_gitignore: bool,
+
+    /// Do not honor .gitignore files, even if configured.
+    #[arg(long, overrides_with = "respect_gitignore")]
+    no_respect_gitignore: bool,
+
+    /// Exclude files matching the given glob pattern. Can be given multiple times.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Include files matching the given glob pattern even if they are excluded otherwise.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+}
+
+#[derive(Args)]
+struct IndexArgs {
+    /// Generate Reed-Solomon parity data with the given overhead (in percent) for repairing bitrot.
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..=100))]
+    parity: Option<u32>,
+
+    /// Record hashes of 1 MiB blocks to locate corrupted byte ranges.
+    #[arg(long, overrides_with = "no_blocks")]
+    blocks: bool,
+
+    /// Do not record block hashes, even if configured.
+    #[arg(long, overrides_with = "blocks")]
+    no_blocks: bool,
+
+    /// Record fingerprints of content-defined chunks to detect files which were moved and modified.
+    #[arg(long, overrides_with = "no_chunks")]
+    chunks: bool,
+
+    /// Do not record chunk fingerprints, even if configured.
+    #[arg(long, overrides_with = "chunks")]
+    no_chunks: bool,
+}
+
+impl IndexArgs {
+    fn resolve(self, config: &Config) -> IndexOptions {
+        IndexOptions {
+            parity: self.parity.or(config.parity),
+            block_hashes: flag(self.blocks, self.no_blocks, config.blocks),
+            chunk_fingerprints: flag(self.chunks, self.no_chunks, config.chunks),
+        }
+    }
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// Show every moved file, also if its whole directory was moved.
+    #[arg(short, long, overrides_with = "no_verbose")]
+    verbose: bool,
+
+    /// Show only the moved directories, even if verbose output is configured.
+    #[arg(long, overrides_with = "verbose")]
+    no_verbose: bool,
+
+    /// Show the number of changed files and the net byte change per directory, aggregating
+    /// directories below the given depth.
+    #[arg(long, value_name = "DEPTH", value_parser = clap::value_parser!(u64).range(1..))]
+    summary: Option<u64>,
+
+    /// Order of the directories of the summary (path (default), changes, or bytes for the largest
+    /// absolute byte change first).
+    #[arg(long, value_name = "ORDER", value_parser = ["path", "changes", "bytes"])]
+    sort: Option<String>,
+}
+
+impl OutputArgs {
+    fn resolve(self, config: &Config) -> Result<OutputOptions> {
+        Ok(OutputOptions {
+            verbose: flag(self.verbose, self.no_verbose, config.verbose),
+            summary_depth: self.summary.or(config.summary).map(|d| d as usize),
+            summary_order: config.sort(self.sort.as_deref())?,
+            report: None,
+            format: Default::default(),
+        })
+    }
+}
+
+impl KeyArgs {
+    /// Resolves the key file given on the command line or the configured one.
+    fn resolve<'a>(&'a self, config: &'a Config) -> Option<&'a str> {
+        config.key(self.key.as_deref())
+    }
+}
+
+impl NormalizationArgs {
+    fn resolve(&self, config: &Config) -> Result<Normalization> {
+        config.normalization(self.normalization.as_deref())
+    }
+}
+
+impl FilterArgs {
+    /// Resolves the filter options. Patterns given on the command line replace the configured
+    /// patterns.
+    fn resolve(self, config: &Config) -> FilterOptions {
+        let or_configured = |args: Vec<String>, configured: &Option<Vec<String>>| if args.is_empty() {
+            configured.clone().unwrap_or_default()
+        } else {
+            args
+        };
+
+        FilterOptions {
+            respect_gitignore: flag(self.respect_gitignore, self.no_respect_gitignore, config.respect_gitignore),
+            exclude: or_configured(self.exclude, &config.exclude),
+            include: or_configured(self.include, &config.include),
+        }
+    }
+}
+
+/// Resolves a boolean option: a flag given on the command line (e.g. `--blocks` or `--no-blocks`)
+/// wins over the configured value.
+fn flag(on: bool, off: bool, configured: Option<bool>) -> bool {
+    if on || off {
+        on
+    } else {
+        configured.unwrap_or(false)
+    }
+}
+
 fn run() -> Result<i32> {
     let opts: Opts = Opts::parse();
 
     match opts.subcmd {
-        SubCommand::Init {directory} => init(&directory),
-        SubCommand::Update {directory} => update(&directory),
-        SubCommand::Audit {directory, update} => audit(&directory, update)
+        SubCommand::Init {directory, index, key, normalization, filter} => {
+            let config = Config::load_for(Path::new(&directory))?;
+            init(&directory, &index.resolve(&config), key.resolve(&config), &filter.resolve(&config),
+                 normalization.resolve(&config)?)
+        }
+        SubCommand::Update {directory, index, key, normalization, filter, output} => {
+            let config = Config::load_for(Path::new(&directory))?;
+            update(&directory, &index.resolve(&config), key.resolve(&config), &filter.resolve(&config),
+                   normalization.resolve(&config)?, &output.resolve(&config)?)
+        }
+        SubCommand::Audit {directory, update, similarity, report, format, key, normalization, filter, output} => {
+            let config = Config::load_for(Path::new(&directory))?;
+            let output = OutputOptions {
+                report: report.map(PathBuf::from),
+                format: config.format(format.as_deref())?,
+                ..output.resolve(&config)?
+            };
+            audit(&directory, update, similarity.or(config.similarity), key.resolve(&config), &filter.resolve(&config),
+                  normalization.resolve(&config)?, &output)
+        }
+        SubCommand::Repair {directory, from, parity, key, normalization, filter} => {
+            let config = Config::load_for(Path::new(&directory))?;
+            repair(&directory, from.as_deref(), parity, key.resolve(&config), &filter.resolve(&config),
+                   normalization.resolve(&config)?)
+        }
+        SubCommand::Sign {directory, key} => sign(&directory, &key),
+        SubCommand::Compare {primary, backup, key, backup_key, normalization} => {
+            let tree = |directory: &str, key: Option<&str>| -> Result<TreeOptions> {
+                let config = Config::load_for(Path::new(directory))?;
+                Ok(TreeOptions {
+                    key_file: config.key(key).map(String::from),
+                    filter: FilterArgs::default().resolve(&config),
+                    normalization: normalization.resolve(&config)?,
+                })
+            };
+            compare(&primary, &backup, &tree(&primary, key.key.as_deref())?, &tree(&backup, backup_key.as_deref())?)
+        }
+        SubCommand::CheckIgnore {directory, paths, filter} => {
+            let config = Config::load_for(Path::new(&directory))?;
+            check_ignore(&directory, &paths, &filter.resolve(&config))
+        }
+        SubCommand::LsFiles {directory, filter} => {
+            let config = Config::load_for(Path::new(&directory))?;
+            ls_files(&directory, &filter.resolve(&config))
+        }
+        SubCommand::Dupes {directory, verify, json, key, normalization, filter} => {
+            let config = Config::load_for(Path::new(&directory))?;
+            dupes(&directory, verify, json, key.resolve(&config), &filter.resolve(&config),
+                  normalization.resolve(&config)?)
+        }
+        SubCommand::Journal {subcmd: JournalCommand::Verify {directory, head, key}} => {
+            let config = Config::load_for(Path::new(&directory))?;
+            journal_verify(&directory, head.as_deref(), key.resolve(&config))
+        }
+        SubCommand::Config {subcmd: ConfigCommand::Show {directory}} => config_show(&directory),
     }
 }
 
@@ -56,7 +445,7 @@ fn main() {
     process::exit(match run() {
         Ok(ret) => ret,
         Err(err) => {
-            eprintln!("{}", err.to_string().bold().red());
+            eprintln!("{}", format!("{:#}", err).bold().red());
             1
         }
     });
@@ -0,0 +1,216 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+use sha2::{Digest, Sha256};
+
+use crate::entry::{Entry, Normalization, to_hex};
+
+/// Path of the root directory in the directory digests.
+pub const ROOT_DIR: &str = ".";
+
+/// Computes a digest for each directory containing index entries. The digest of a directory is the
+/// hash over the sorted names of its children together with their hashes (files) or digests
+/// (sub directories). Directories are identified by their path relative to the root in NFC,
+/// regardless of the normalization form used by the index, so that the digests do not depend on
+/// it. Children whose names only differ in their normalization form are all kept.
+pub fn directory_digests(entries: &[Entry]) -> BTreeMap<String, String> {
+    // Children by their NFC name and raw name
+    let mut children: BTreeMap<String, BTreeMap<(String, String), Option<&str>>> = BTreeMap::new();
+    children.entry(ROOT_DIR.to_owned()).or_default();
+
+    for e in entries {
+        let path = Normalization::Nfc.normalize(&e.path);
+        let raw_path = e.path.to_string_lossy();
+        let (mut dir, name) = split(&path);
+        let (mut raw_dir, raw_name) = split(&raw_path);
+        children.entry(dir.to_owned()).or_default().
+            insert((name.to_owned(), raw_name.to_owned()), Some(&e.hash));
+
+        while dir != ROOT_DIR {
+            let (parent, name) = split(dir);
+            let (raw_parent, raw_name) = split(raw_dir);
+            children.entry(parent.to_owned()).or_default().
+                insert((name.to_owned(), raw_name.to_owned()), None);
+            dir = parent;
+            raw_dir = raw_parent;
+        }
+    }
+
+    let mut dirs = children.keys().cloned().collect::<Vec<String>>();
+    dirs.sort_by_key(|d| Reverse(depth(d)));
+
+    let mut digests = BTreeMap::new();
+    for dir in dirs {
+        let mut hasher = Sha256::new();
+        for ((name, _), hash) in children[&dir].iter() {
+            match hash {
+                Some(hash) => hasher.input(format!("f {}  {}\n", hash, name)),
+                None => hasher.input(format!("d {}  {}\n", digests[&join(&dir, name)], name)),
+            }
+        }
+        digests.insert(dir, to_hex(&hasher.result()));
+    }
+
+    digests
+}
+
+/// Computes the digest of the root directory over all index entries.
+pub fn root_hash(entries: &[Entry]) -> String {
+    directory_digests(entries).remove(ROOT_DIR).unwrap_or_default()
+}
+
+/// Returns all directories whose digests differ between both sides. Files located directly in
+/// other directories are guaranteed to be identical on both sides.
+pub fn diverging_dirs(a: &BTreeMap<String, String>, b: &BTreeMap<String, String>) -> BTreeSet<String> {
+    let mut diverging = BTreeSet::new();
+    let mut pending = vec![ROOT_DIR.to_owned()];
+
+    while let Some(dir) = pending.pop() {
+        if diverging.contains(&dir) || a.get(&dir) == b.get(&dir) {
+            continue;
+        }
+
+        let prefix = join(&dir, "");
+        pending.extend(sub_dirs(a, &prefix).chain(sub_dirs(b, &prefix)).cloned());
+        diverging.insert(dir);
+    }
+
+    diverging
+}
+
+/// Returns the direct sub directories of the directory with the given prefix. Only the range of
+/// keys starting with the prefix is visited.
+fn sub_dirs<'a>(digests: &'a BTreeMap<String, String>, prefix: &'a str) -> impl Iterator<Item=&'a String> {
+    digests.range::<str, _>((Bound::Included(prefix), Bound::Unbounded)).
+        map(|(dir, _)| dir).
+        take_while(move |dir| dir.starts_with(prefix)).
+        filter(move |dir| {
+            let name = &dir[prefix.len()..];
+            dir.as_str() != ROOT_DIR && !name.is_empty() && !name.contains('/')
+        })
+}
+
+/// Returns the directory of the given index entry as used in the directory digests.
+pub fn parent_dir(entry: &Entry) -> String {
+    split(&Normalization::Nfc.normalize(&entry.path)).0.to_owned()
+}
+
+fn split(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => (ROOT_DIR, path)
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir == ROOT_DIR {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn depth(dir: &str) -> usize {
+    if dir == ROOT_DIR {
+        0
+    } else {
+        dir.matches('/').count() + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entry::given_entry;
+
+    use super::*;
+
+    #[test]
+    fn test_directory_digests() {
+        let entries = vec![
+            given_entry("a/b/c.txt", "1", 123, 123),
+            given_entry("a/d.txt", "2", 123, 123),
+            given_entry("e.txt", "3", 123, 123),
+        ];
+
+        let digests = directory_digests(&entries);
+
+        assert_eq!(digests.keys().collect::<Vec<&String>>(), vec![".", "a", "a/b"]);
+        assert_eq!(digests["a/b"], to_hex(&Sha256::digest(b"f 1  c.txt\n")));
+
+        let a = format!("d {}  b\nf 2  d.txt\n", digests["a/b"]);
+        assert_eq!(digests["a"], to_hex(&Sha256::digest(a.as_bytes())));
+
+        let root = format!("d {}  a\nf 3  e.txt\n", digests["a"]);
+        assert_eq!(digests["."], to_hex(&Sha256::digest(root.as_bytes())));
+    }
+
+    #[test]
+    fn test_directory_digests_normalization() {
+        let nfc = given_entry("caf\u{e9}/a.txt", "1", 123, 123);
+        let nfd = given_entry("cafe\u{301}/a.txt", "1", 123, 123);
+        let other = given_entry("cafe\u{301}/a.txt", "2", 123, 123);
+
+        // Replicas with different normalization forms have the same digests
+        assert_eq!(directory_digests(&[nfc.clone()]), directory_digests(&[nfd.clone()]));
+        assert_eq!(parent_dir(&nfd), "caf\u{e9}");
+
+        // Colliding names are all part of the digest
+        assert_ne!(directory_digests(&[nfc.clone(), other]), directory_digests(&[nfc]));
+    }
+
+    #[test]
+    fn test_directory_digests_empty() {
+        let digests = directory_digests(&[]);
+
+        assert_eq!(digests.len(), 1);
+        assert_eq!(digests["."], to_hex(&Sha256::digest(b"")));
+    }
+
+    #[test]
+    fn test_diverging_dirs() {
+        let a = directory_digests(&[
+            given_entry("a/b/c.txt", "1", 123, 123),
+            given_entry("a/d.txt", "2", 123, 123),
+            given_entry("x/y.txt", "3", 123, 123),
+            given_entry("z/1.txt", "4", 123, 123),
+        ]);
+        let b = directory_digests(&[
+            given_entry("a/b/c.txt", "1", 123, 123),
+            given_entry("a/d.txt", "2", 123, 123),
+            given_entry("x/y.txt", "changed", 123, 123),
+            given_entry("z/2/1.txt", "4", 123, 123),
+        ]);
+
+        let diverging = diverging_dirs(&a, &b);
+
+        assert_eq!(diverging.into_iter().collect::<Vec<String>>(), vec![".", "x", "z", "z/2"]);
+    }
+
+    #[test]
+    fn test_diverging_dirs_identical() {
+        let a = directory_digests(&[given_entry("a/b/c.txt", "1", 123, 123)]);
+
+        assert!(diverging_dirs(&a, &a.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_sub_dirs() {
+        let digests = directory_digests(&[
+            given_entry("a/b/c.txt", "1", 123, 123),
+            given_entry("a-b/d.txt", "2", 123, 123),
+            given_entry("a/e/f/g.txt", "3", 123, 123),
+            given_entry("h.txt", "4", 123, 123),
+        ]);
+
+        assert_eq!(sub_dirs(&digests, "").collect::<Vec<&String>>(), vec!["a", "a-b"]);
+        assert_eq!(sub_dirs(&digests, "a/").collect::<Vec<&String>>(), vec!["a/b", "a/e"]);
+        assert_eq!(sub_dirs(&digests, "a/b/").count(), 0);
+    }
+
+    #[test]
+    fn test_parent_dir() {
+        assert_eq!(parent_dir(&given_entry("a/b/c.txt", "1", 123, 123)), "a/b");
+        assert_eq!(parent_dir(&given_entry("c.txt", "1", 123, 123)), ".");
+    }
+}
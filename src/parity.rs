@@ -0,0 +1,468 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
+
+use crate::entry::{Entry, HashingReader};
+
+pub const PARITY_DIR_NAME: &str = ".auditr-parity";
+pub const DEFAULT_PARITY_PERCENT: u32 = 10;
+
+/// File in the parity directory storing the configured overhead in percent.
+const PERCENT_FILENAME: &str = "percent";
+
+const MAGIC: &[u8; 8] = b"AUDITRP1";
+const HEADER_LEN: usize = 8 + 4 + 8 + 8;
+const DIGEST_LEN: usize = 32;
+const MAX_SHARD_SIZE: u64 = 64 * 1024;
+const STRIPE_SHARDS: u64 = 64;
+
+/// Layout of the parity data of a single file. The file is split into stripes of up to
+/// `STRIPE_SHARDS` data shards; each stripe is protected by its own set of parity shards.
+struct Layout {
+    percent: u32,
+    shard_size: u64,
+    len: u64,
+}
+
+impl Layout {
+    fn new(percent: u32, len: u64) -> Layout {
+        let shard_size = len.div_ceil(STRIPE_SHARDS).clamp(1, MAX_SHARD_SIZE);
+        Layout {
+            percent,
+            shard_size,
+            len,
+        }
+    }
+
+    fn stripes(&self) -> impl Iterator<Item=(u64, usize, usize)> {
+        let stripe_len = self.shard_size * STRIPE_SHARDS;
+        let len = self.len;
+        let shard_size = self.shard_size;
+        let percent = self.percent as usize;
+        (0..len.div_ceil(stripe_len)).
+            map(move |i| {
+                let offset = i * stripe_len;
+                let data_len = (len - offset).min(stripe_len);
+                let data_shards = data_len.div_ceil(shard_size) as usize;
+                let parity_shards = (data_shards * percent).div_ceil(100).max(1);
+                (offset, data_shards, parity_shards)
+            })
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.percent.to_le_bytes())?;
+        writer.write_all(&self.shard_size.to_le_bytes())?;
+        writer.write_all(&self.len.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn read(reader: &mut dyn Read) -> Result<Layout> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        if &header[0..8] != MAGIC {
+            bail!("invalid parity file");
+        }
+
+        Ok(Layout {
+            percent: u32::from_le_bytes(header[8..12].try_into()?),
+            shard_size: u64::from_le_bytes(header[12..20].try_into()?),
+            len: u64::from_le_bytes(header[20..28].try_into()?),
+        })
+    }
+}
+
+pub fn parity_path(root: &Path, hash: &str) -> PathBuf {
+    root.join(PARITY_DIR_NAME).join(hash)
+}
+
+/// Synchronizes the parity data with the given index entries. Parity data of files which are
+/// no longer part of the index is removed. Parity data is only created if `percent` is given or
+/// parity data was created for the directory before, in which case the overhead it was created
+/// with is used. Returns the entries whose files could not be read or do not match the indexed
+/// hash, together with the reason; no parity data is created for them.
+pub fn update<'a>(root: &Path, entries: &'a [Entry], percent: Option<u32>) -> Result<Vec<(&'a Entry, anyhow::Error)>> {
+    let dir = root.join(PARITY_DIR_NAME);
+    if percent.is_none() && !dir.exists() {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(&dir)?;
+
+    let percent = match percent {
+        Some(p) => p,
+        None => stored_percent(&dir)?
+    };
+    fs::write(dir.join(PERCENT_FILENAME), format!("{}\n", percent))?;
+
+    let hashes = entries.iter().
+        map(|e| e.hash.as_str()).
+        collect::<HashSet<&str>>();
+    for file in fs::read_dir(&dir)? {
+        let file = file?;
+        let name = file.file_name();
+        if name != PERCENT_FILENAME && !hashes.contains(name.to_string_lossy().as_ref()) {
+            fs::remove_file(file.path())?;
+        }
+    }
+
+    let mut failed = Vec::new();
+    for e in entries.iter().filter(|e| e.len > 0) {
+        let path = parity_path(root, &e.hash);
+        if path.exists() && read_percent(&path)? == percent {
+            continue;
+        }
+
+        match generate(root, e, percent) {
+            Ok(true) => {}
+            Ok(false) => failed.push((e, anyhow!("contents do not match the indexed hash"))),
+            Err(err) => failed.push((e, err)),
+        }
+    }
+
+    Ok(failed)
+}
+
+/// Returns the overhead the parity data in the given directory was created with. Directories
+/// created before the overhead was stored use the overhead of any of their parity files.
+fn stored_percent(dir: &Path) -> Result<u32> {
+    let path = dir.join(PERCENT_FILENAME);
+    if path.exists() {
+        let contents = fs::read_to_string(&path)?;
+        return contents.trim().parse().
+            with_context(|| format!("Invalid parity overhead in '{}'", path.display()));
+    }
+
+    for file in fs::read_dir(dir)? {
+        if let Ok(percent) = read_percent(&file?.path()) {
+            return Ok(percent);
+        }
+    }
+    Ok(DEFAULT_PARITY_PERCENT)
+}
+
+/// Computes the parity data of the file of the given entry with `percent` overhead. Returns false
+/// and keeps any existing parity data if the contents of the file do not match the indexed hash.
+pub fn generate(root: &Path, entry: &Entry, percent: u32) -> Result<bool> {
+    let path = parity_path(root, &entry.hash);
+    let tmp_path = path.with_extension("tmp");
+
+    let result = write_parity(root, entry, percent, &tmp_path);
+    match result {
+        Ok(true) => fs::rename(&tmp_path, &path)?,
+        _ => {
+            let _ = fs::remove_file(&tmp_path);
+        }
+    }
+    result
+}
+
+/// Writes the parity data of the file of the given entry to `path`. Returns whether the contents
+/// read match the indexed hash.
+fn write_parity(root: &Path, entry: &Entry, percent: u32, path: &Path) -> Result<bool> {
+    let layout = Layout::new(percent, entry.len);
+    let mut reader = HashingReader::new(BufReader::new(File::open(root.join(&entry.path))?));
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    layout.write(&mut writer)?;
+
+    for (_, data_shards, parity_shards) in layout.stripes() {
+        let mut shards = read_shards(&mut reader, data_shards, layout.shard_size)?;
+        for shard in shards.iter() {
+            writer.write_all(Sha256::digest(shard).as_slice())?;
+        }
+
+        shards.resize(data_shards + parity_shards, vec![0; layout.shard_size as usize]);
+        ReedSolomon::new(data_shards, parity_shards)?.encode(&mut shards)?;
+
+        for shard in shards[data_shards..].iter() {
+            writer.write_all(Sha256::digest(shard).as_slice())?;
+            writer.write_all(shard)?;
+        }
+    }
+
+    writer.flush()?;
+    reader.matches(&entry.hash)
+}
+
+/// Reconstructs corrupted parts of the file of the given entry in place using its parity data and
+/// restores the indexed modification timestamp. Returns whether the contents of the file match
+/// the indexed hash afterwards.
+pub fn repair(root: &Path, entry: &Entry) -> Result<bool> {
+    let path = parity_path(root, &entry.hash);
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    let mut parity = BufReader::new(File::open(path)?);
+    let layout = Layout::read(&mut parity)?;
+    if layout.len != entry.len {
+        bail!("parity data does not match index entry '{}'", entry);
+    }
+
+    let file_path = root.join(&entry.path);
+    let mut file = File::options().read(true).write(true).open(&file_path)?;
+    let shard_size = layout.shard_size as usize;
+
+    for (offset, data_shards, parity_shards) in layout.stripes() {
+        file.seek(SeekFrom::Start(offset))?;
+        let data = read_shards(&mut file, data_shards, layout.shard_size)?;
+
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(data_shards + parity_shards);
+        for shard in data {
+            let digest = read_digest(&mut parity)?;
+            shards.push(Some(shard).filter(|s| Sha256::digest(s).as_slice() == digest));
+        }
+        for _ in 0..parity_shards {
+            let digest = read_digest(&mut parity)?;
+            let mut shard = vec![0; shard_size];
+            parity.read_exact(&mut shard)?;
+            shards.push(Some(shard).filter(|s| Sha256::digest(s).as_slice() == digest));
+        }
+
+        let corrupted = (0..data_shards).
+            filter(|&i| shards[i].is_none()).
+            collect::<Vec<usize>>();
+        if corrupted.is_empty() {
+            continue;
+        }
+        if shards.iter().filter(|s| s.is_none()).count() > parity_shards {
+            return Ok(false);
+        }
+
+        ReedSolomon::new(data_shards, parity_shards)?.reconstruct_data(&mut shards)?;
+
+        for i in corrupted {
+            let shard_offset = offset + (i * shard_size) as u64;
+            let len = (layout.len - shard_offset).min(layout.shard_size) as usize;
+            if let Some(shard) = &shards[i] {
+                file.seek(SeekFrom::Start(shard_offset))?;
+                file.write_all(&shard[..len])?;
+            }
+        }
+    }
+
+    file.set_len(layout.len)?;
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(entry.modified))?;
+    drop(file);
+
+    let mut actual = Entry::from_path(&entry.path);
+    actual.update_hash(root, true, &mut |_| ())?;
+    Ok(actual.hash == entry.hash)
+}
+
+fn read_percent(path: &Path) -> Result<u32> {
+    let mut file = File::open(path)?;
+    Ok(Layout::read(&mut file)?.percent)
+}
+
+fn read_digest(reader: &mut dyn Read) -> Result<[u8; DIGEST_LEN]> {
+    let mut digest = [0; DIGEST_LEN];
+    reader.read_exact(&mut digest)?;
+    Ok(digest)
+}
+
+fn read_shards<R: Read>(reader: &mut R, count: usize, shard_size: u64) -> Result<Vec<Vec<u8>>> {
+    let mut shards = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut shard = Vec::with_capacity(shard_size as usize);
+        reader.by_ref().take(shard_size).read_to_end(&mut shard)?;
+        shard.resize(shard_size as usize, 0);
+        shards.push(shard);
+    }
+    Ok(shards)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::entry::given_file_entry;
+
+    use super::*;
+
+    fn given_contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_layout_stripes() {
+        let layout = Layout::new(10, 10 * 1024 * 1024);
+        let stripes = layout.stripes().collect::<Vec<(u64, usize, usize)>>();
+
+        assert_eq!(layout.shard_size, MAX_SHARD_SIZE);
+        assert_eq!(stripes.len(), 3);
+        assert_eq!(stripes[0], (0, 64, 7));
+        assert_eq!(stripes[1], (4 * 1024 * 1024, 64, 7));
+        assert_eq!(stripes[2], (8 * 1024 * 1024, 32, 4));
+    }
+
+    #[test]
+    fn test_layout_small_file() {
+        let layout = Layout::new(50, 10);
+        let stripes = layout.stripes().collect::<Vec<(u64, usize, usize)>>();
+
+        assert_eq!(layout.shard_size, 1);
+        assert_eq!(stripes, vec![(0, 10, 5)]);
+    }
+
+    #[test]
+    fn test_repair() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        fs::create_dir(root.path().join(PARITY_DIR_NAME))?;
+        let contents = given_contents(1024 * 1024 + 17);
+        let entry = given_file_entry(root.path(), "a.bin", &contents)?;
+        assert!(generate(root.path(), &entry, 10)?);
+
+        let mut rotten = contents.clone();
+        rotten[1000] ^= 0xff;
+        rotten[1024 * 1024 + 3] ^= 0xff;
+        fs::write(root.path().join("a.bin"), &rotten)?;
+
+        // When
+        let repaired = repair(root.path(), &entry)?;
+
+        // Then
+        assert!(repaired);
+        assert_eq!(fs::read(root.path().join("a.bin"))?, contents);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_too_much_corruption() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        fs::create_dir(root.path().join(PARITY_DIR_NAME))?;
+        let contents = given_contents(64 * 100);
+        let entry = given_file_entry(root.path(), "a.bin", &contents)?;
+        assert!(generate(root.path(), &entry, 10)?);
+
+        let rotten = vec![0; contents.len()];
+        fs::write(root.path().join("a.bin"), &rotten)?;
+
+        // When
+        let repaired = repair(root.path(), &entry)?;
+
+        // Then
+        assert!(!repaired);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_without_parity() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let entry = given_file_entry(root.path(), "a.bin", b"contents")?;
+
+        // When
+        let repaired = repair(root.path(), &entry)?;
+
+        // Then
+        assert!(!repaired);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let a = given_file_entry(root.path(), "a.bin", b"contents a")?;
+        let b = given_file_entry(root.path(), "b.bin", b"contents b")?;
+        let empty = given_file_entry(root.path(), "empty.bin", b"")?;
+
+        // When
+        update(root.path(), &[a.clone(), empty.clone()], Some(20))?;
+        update(root.path(), &[b.clone(), empty.clone()], None)?;
+
+        // Then
+        assert!(!parity_path(root.path(), &a.hash).exists());
+        assert!(!parity_path(root.path(), &empty.hash).exists());
+        assert_eq!(read_percent(&parity_path(root.path(), &b.hash))?, 20);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_rotten() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let a = given_file_entry(root.path(), "a.bin", b"contents a")?;
+        fs::write(root.path().join("a.bin"), b"contents x")?;
+
+        // When
+        let failed = update(root.path(), std::slice::from_ref(&a), Some(20))?;
+
+        // Then
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, &a);
+        assert_eq!(failed[0].1.to_string(), "contents do not match the indexed hash");
+        assert!(!parity_path(root.path(), &a.hash).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_missing_file() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let a = given_file_entry(root.path(), "a.bin", b"contents a")?;
+        let b = given_file_entry(root.path(), "b.bin", b"contents b")?;
+        fs::remove_file(root.path().join("a.bin"))?;
+
+        // When
+        let failed = update(root.path(), &[a.clone(), b.clone()], Some(20))?;
+
+        // Then
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, &a);
+        assert!(!parity_path(root.path(), &a.hash).with_extension("tmp").exists());
+        assert!(parity_path(root.path(), &b.hash).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_without_stored_percent() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let a = given_file_entry(root.path(), "a.bin", b"contents a")?;
+        let b = given_file_entry(root.path(), "b.bin", b"contents b")?;
+        update(root.path(), std::slice::from_ref(&a), Some(30))?;
+        fs::remove_file(root.path().join(PARITY_DIR_NAME).join(PERCENT_FILENAME))?;
+
+        // When
+        update(root.path(), &[a, b.clone()], None)?;
+
+        // Then
+        assert_eq!(read_percent(&parity_path(root.path(), &b.hash))?, 30);
+        assert_eq!(stored_percent(&root.path().join(PARITY_DIR_NAME))?, 30);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_disabled() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let a = given_file_entry(root.path(), "a.bin", b"contents a")?;
+
+        // When
+        update(root.path(), &[a], None)?;
+
+        // Then
+        assert!(!root.path().join(PARITY_DIR_NAME).exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::analyze;
+use crate::blocks;
+use crate::entry::Entry;
+use crate::filter;
+use crate::index;
+
+const REPAIR_FILE_SUFFIX: &str = ".auditr-repair";
+
+/// A mirror copy of a directory tree that is used as source for repairing files.
+pub struct Mirror<'a> {
+    root: &'a Path,
+    by_hash: Option<HashMap<String, Vec<PathBuf>>>,
+}
+
+impl<'a> Mirror<'a> {
+    pub fn new(root: &'a Path) -> Mirror<'a> {
+        Mirror {
+            root,
+            by_hash: None,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        self.root
+    }
+
+    /// Finds a file in the mirror with the same contents as the given index entry.
+    /// The file at the same path is preferred, otherwise all files of the mirror are searched.
+    /// Each candidate is re-hashed before it is returned.
+    pub fn find(&mut self, expected: &Entry) -> Result<Option<PathBuf>> {
+        if self.verify(&expected.path, &expected.hash)? {
+            return Ok(Some(expected.path.clone()));
+        }
+
+        for candidate in self.candidates(&expected.hash)? {
+            if candidate != expected.path && self.verify(&candidate, &expected.hash)? {
+                return Ok(Some(candidate));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn verify(&self, path: &Path, hash: &str) -> Result<bool> {
+        if !self.root.join(path).is_file() {
+            return Ok(false);
+        }
+
+        let mut e = Entry::from_path(path);
+        e.update_hash(self.root, true, &mut |_| ())?;
+        Ok(e.hash == hash)
+    }
+
+    fn candidates(&mut self, hash: &str) -> Result<Vec<PathBuf>> {
+        if self.by_hash.is_none() {
+            self.by_hash = Some(self.load_hashes()?);
+        }
+
+        Ok(self.by_hash.as_ref().
+            and_then(|h| h.get(hash)).
+            cloned().
+            unwrap_or_default())
+    }
+
+    fn load_hashes(&self) -> Result<HashMap<String, Vec<PathBuf>>> {
+        let filter = filter::load_filter(self.root)?;
+        let entries = if index::index_exists(self.root) {
+            index::load(self.root, filter.as_ref())?
+        } else {
+            analyze::analyze_dir(self.root, filter.as_ref(), false, true, |_| ())?
+        };
+
+        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for e in entries {
+            by_hash.entry(e.hash).or_default().push(e.path);
+        }
+        Ok(by_hash)
+    }
+}
+
+/// Replaces the file of the given index entry with a copy of `source` and restores the indexed
+/// modification timestamp. If block hashes were recorded for the entry, only the corrupted blocks
+/// are copied. Returns whether the contents of the repaired file match the indexed hash.
+pub fn restore(root: &Path, source: &Path, expected: &Entry) -> Result<bool> {
+    if let Some(ranges) = blocks::corrupted_ranges(root, expected)? {
+        blocks::copy_ranges(root, source, expected, &ranges)?;
+        File::options().write(true).open(root.join(&expected.path))?.
+            set_modified(UNIX_EPOCH + Duration::from_secs(expected.modified))?;
+        return verify(root, expected);
+    }
+
+    let target = root.join(&expected.path);
+    let mut tmp_name = target.clone().into_os_string();
+    tmp_name.push(REPAIR_FILE_SUFFIX);
+    let tmp = PathBuf::from(tmp_name);
+
+    fs::copy(source, &tmp)?;
+    File::options().write(true).open(&tmp)?.
+        set_modified(UNIX_EPOCH + Duration::from_secs(expected.modified))?;
+    fs::rename(&tmp, &target)?;
+
+    verify(root, expected)
+}
+
+fn verify(root: &Path, expected: &Entry) -> Result<bool> {
+    let mut actual = Entry::from_path(&expected.path);
+    actual.update_hash(root, true, &mut |_| ())?;
+    Ok(actual.hash == expected.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::entry::given_file_entry;
+
+    use super::*;
+
+    #[test]
+    fn test_find_same_path() -> Result<()> {
+        // Given
+        let mirror_dir = tempdir()?;
+        let expected = given_file_entry(mirror_dir.path(), "a.txt", "contents")?;
+        given_file_entry(mirror_dir.path(), "b.txt", "contents")?;
+
+        // When
+        let found = Mirror::new(mirror_dir.path()).find(&expected)?;
+
+        // Then
+        assert_eq!(found, Some(PathBuf::from("a.txt")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_other_path() -> Result<()> {
+        // Given
+        let mirror_dir = tempdir()?;
+        let expected = given_file_entry(mirror_dir.path(), "a.txt", "contents")?;
+        fs::write(mirror_dir.path().join("a.txt"), "other contents")?;
+        given_file_entry(mirror_dir.path(), "b.txt", "contents")?;
+
+        // When
+        let found = Mirror::new(mirror_dir.path()).find(&expected)?;
+
+        // Then
+        assert_eq!(found, Some(PathBuf::from("b.txt")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_not_found() -> Result<()> {
+        // Given
+        let mirror_dir = tempdir()?;
+        let expected = given_file_entry(mirror_dir.path(), "a.txt", "contents")?;
+        fs::write(mirror_dir.path().join("a.txt"), "other contents")?;
+
+        // When
+        let found = Mirror::new(mirror_dir.path()).find(&expected)?;
+
+        // Then
+        assert_eq!(found, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore() -> Result<()> {
+        // Given
+        let root = tempdir()?;
+        let mirror_dir = tempdir()?;
+        let mut expected = given_file_entry(root.path(), "a.txt", "contents")?;
+        expected.modified = 1234567;
+        fs::write(root.path().join("a.txt"), "rotten")?;
+        given_file_entry(mirror_dir.path(), "a.txt", "contents")?;
+
+        // When
+        let repaired = restore(root.path(), &mirror_dir.path().join("a.txt"), &expected)?;
+
+        // Then
+        assert!(repaired);
+        assert_eq!(fs::read_to_string(root.path().join("a.txt"))?, "contents");
+
+        let mut actual = Entry::from_path(Path::new("a.txt"));
+        actual.update_meta(root.path())?;
+        assert_eq!(actual.modified, 1234567);
+        assert!(!root.path().join("a.txt.auditr-repair").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::entry::Entry;
+use crate::stats::Stats;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+td.num { text-align: right; }
+.status-ok { color: #2a7d2a; }
+.status-failed { color: #b00; }
+.bitrot td { background: #fdd; font-weight: bold; }
+ul.tree { list-style: none; padding-left: 1.2em; }
+li.bitrot { background: #fdd; font-weight: bold; }
+code { font-size: 0.95em; }
+.meta { color: #666; font-size: 0.9em; }
+";
+
+/// A single changed file of the report, with its index entry before and its entry after the
+/// change (if any).
+struct Change<'a> {
+    symbol: &'static str,
+    class: &'static str,
+    old: Option<&'a Entry>,
+    new: Option<&'a Entry>,
+    note: String,
+}
+
+impl<'a> Change<'a> {
+    fn path(&self) -> &'a Path {
+        self.new.or(self.old).map(|e| e.path.as_path()).unwrap_or_else(|| Path::new(""))
+    }
+}
+
+#[derive(Default)]
+struct Node<'a> {
+    dirs: BTreeMap<String, Node<'a>>,
+    files: Vec<&'a Change<'a>>,
+}
+
+impl<'a> Node<'a> {
+    fn insert(&mut self, change: &'a Change<'a>) {
+        let path = change.path();
+        let mut node = self;
+        if let Some(parent) = path.parent() {
+            for c in parent.components() {
+                node = node.dirs.entry(c.as_os_str().to_string_lossy().into_owned()).or_default();
+            }
+        }
+        node.files.push(change);
+    }
+
+    fn len(&self) -> usize {
+        self.files.len() + self.dirs.values().map(|d| d.len()).sum::<usize>()
+    }
+}
+
+/// Creates a self-contained HTML report of the given stats. The previous index entries of updated
+/// files are looked up in the given entries, which must be sorted.
+pub fn html(root: &Path, stats: &Stats, entries: &[Entry]) -> String {
+    let changes = changes(stats, entries);
+    let mut out = String::new();
+
+    let (status, class) = if !stats.updated_bitrot.is_empty() {
+        ("Audit failed - bitrot detected!", "status-failed")
+    } else if stats.modified() {
+        ("Audit failed - difference detected!", "status-failed")
+    } else {
+        ("Audit successful.", "status-ok")
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(out, "<title>Audit report: {}</title>", escape(&root.to_string_lossy()));
+    let _ = writeln!(out, "<style>{}</style>\n</head>\n<body>", STYLE);
+    let _ = writeln!(out, "<h1>Audit report</h1>");
+    let _ = writeln!(out, "<p>Directory: <code>{}</code><br>Created: {}</p>",
+                     escape(&root.to_string_lossy()), format_time(now));
+    let _ = writeln!(out, "<p class=\"{}\"><strong>{}</strong></p>", class, status);
+
+    out.push_str("<h2>Summary</h2>\n<table>\n");
+    let counts = [
+        ("New", stats.added.len()),
+        ("Copied", stats.copied.len()),
+        ("Updated", stats.updated.len()),
+        ("Updated (bitrot)", stats.updated_bitrot.len()),
+        ("Removed", stats.removed.len()),
+        ("Moved", stats.moved.len()),
+        ("Moved+modified", stats.moved_modified.len()),
+        ("Renamed (NFC/NFD)", stats.renamed.len()),
+        ("Unchanged", stats.unchanged.len()),
+        ("Total", stats.total as usize),
+    ];
+    for (name, count) in counts.iter() {
+        let _ = writeln!(out, "<tr><th>{}</th><td class=\"num\">{}</td></tr>", name, count);
+    }
+    out.push_str("</table>\n");
+
+    if !stats.updated_bitrot.is_empty() {
+        out.push_str("<h2>Bitrot</h2>\n<table>\n");
+        out.push_str("<tr><th>File</th><th>Size</th><th>Modified</th><th>Hash before</th><th>Hash after</th></tr>\n");
+        for c in changes.iter().filter(|c| c.class == "bitrot") {
+            let old = c.old.or(c.new).unwrap();
+            let new = c.new.unwrap();
+            let _ = writeln!(out, "<tr class=\"bitrot\"><td>{}</td><td class=\"num\">{}</td><td>{}</td><td><code>{}</code></td><td><code>{}</code></td></tr>",
+                             escape(&new.to_string()), new.len, format_time(new.modified), old.hash, new.hash);
+        }
+        out.push_str("</table>\n");
+    }
+
+    let moves = changes.iter().filter(|c| c.class == "moved").collect::<Vec<&Change>>();
+    if !moves.is_empty() {
+        out.push_str("<h2>Moved files</h2>\n<table>\n");
+        out.push_str("<tr><th>From</th><th>To</th><th>Size before</th><th>Size after</th><th>Similarity</th></tr>\n");
+        for c in moves {
+            let (old, new) = (c.old.unwrap(), c.new.unwrap());
+            let similarity = if c.symbol == "%" { c.note.as_str() } else { "identical" };
+            let _ = writeln!(out, "<tr><td>{}</td><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td></tr>",
+                             escape(&old.path.to_string_lossy()), escape(&new.to_string()), old.len, new.len, similarity);
+        }
+        out.push_str("</table>\n");
+    }
+
+    out.push_str("<h2>Changes</h2>\n");
+    if changes.is_empty() {
+        out.push_str("<p>No changes.</p>\n");
+    } else {
+        let mut tree = Node::default();
+        for c in changes.iter() {
+            tree.insert(c);
+        }
+        write_tree(&mut out, &tree);
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn changes<'a>(stats: &Stats<'a>, entries: &'a [Entry]) -> Vec<Change<'a>> {
+    let indexed = |e: &Entry| entries.binary_search(e).ok().map(|i| &entries[i]);
+    let by_path = entries.iter().
+        map(|e| (e.path.as_path(), e)).
+        collect::<HashMap<&Path, &Entry>>();
+    let mut changes = Vec::new();
+
+    for &e in stats.added.iter() {
+        changes.push(Change { symbol: "+", class: "added", old: None, new: Some(e), note: String::new() });
+    }
+    for &(copy, original) in stats.copied.iter() {
+        changes.push(Change { symbol: "=", class: "added", old: None, new: Some(copy), note: format!("copy of {}", original) });
+    }
+    for &e in stats.updated.iter() {
+        changes.push(Change { symbol: "*", class: "updated", old: indexed(e), new: Some(e), note: String::new() });
+    }
+    for &e in stats.updated_bitrot.iter() {
+        changes.push(Change { symbol: "!", class: "bitrot", old: indexed(e), new: Some(e), note: String::from("bitrot") });
+    }
+    for &e in stats.removed.iter() {
+        changes.push(Change { symbol: "-", class: "removed", old: Some(e), new: None, note: String::new() });
+    }
+    for (old, &new) in stats.moved.iter().chain(stats.renamed.iter()) {
+        let note = format!("from {}", old.to_string_lossy());
+        let old = by_path.get(old.as_path()).copied().unwrap_or(new);
+        changes.push(Change { symbol: ">", class: "moved", old: Some(old), new: Some(new), note });
+    }
+    for &(old, new, similarity) in stats.moved_modified.iter() {
+        changes.push(Change { symbol: "%", class: "moved", old: Some(old), new: Some(new), note: format!("{}% similar", similarity) });
+    }
+
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    changes
+}
+
+fn write_tree(out: &mut String, node: &Node) {
+    out.push_str("<ul class=\"tree\">\n");
+    for (name, dir) in node.dirs.iter() {
+        let _ = writeln!(out, "<li><details open><summary>{}/ <span class=\"meta\">({} files)</span></summary>",
+                         escape(name), dir.len());
+        write_tree(out, dir);
+        out.push_str("</details></li>\n");
+    }
+    for c in node.files.iter() {
+        let name = c.path().file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let _ = write!(out, "<li class=\"{}\">[{}] {}", c.class, c.symbol, escape(&name));
+        if !c.note.is_empty() {
+            let _ = write!(out, " ({})", escape(&c.note));
+        }
+        let _ = writeln!(out, " <span class=\"meta\">{}</span></li>", describe(c.old, c.new));
+    }
+    out.push_str("</ul>\n");
+}
+
+/// Describes the sizes and timestamps before and after a change.
+fn describe(old: Option<&Entry>, new: Option<&Entry>) -> String {
+    let size = |e: Option<&Entry>| e.map(|e| format!("{} bytes", e.len)).unwrap_or_else(|| String::from("-"));
+    let time = |e: Option<&Entry>| e.map(|e| format_time(e.modified)).unwrap_or_else(|| String::from("-"));
+    format!("size: {} &rarr; {}, modified: {} &rarr; {}", size(old), size(new), time(old), time(new))
+}
+
+/// Formats the given Unix timestamp as UTC date and time.
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Converts days since 1970-01-01 to a civil date (proleptic Gregorian calendar).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").
+        replace('<', "&lt;").
+        replace('>', "&gt;").
+        replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::FromIterator;
+
+    use crate::diff::Event;
+    use crate::entry::given_entry;
+
+    use super::*;
+
+    #[test]
+    fn test_html() {
+        // Given
+        let old = vec![
+            given_entry("a/<moved>.txt", "h1", 100, 0),
+            given_entry("b/rotten.txt", "h2", 100, 0),
+        ];
+        let moved = given_entry("c/<moved>.txt", "h1", 100, 0);
+        let rotten = given_entry("b/rotten.txt", "h3", 100, 0);
+        let stats = Stats::from_iter(vec![
+            Event::REMOVED { old: &old[0] },
+            Event::UPDATED { old: &old[1], new: &rotten },
+            Event::ADDED { new: &moved },
+        ]);
+
+        // When
+        let html = html(Path::new("/data"), &stats, &old);
+
+        // Then
+        assert!(html.contains("Audit failed - bitrot detected!"));
+        assert!(html.contains("<tr class=\"bitrot\"><td>b/rotten.txt</td><td class=\"num\">100</td><td>1970-01-01 00:00:00 UTC</td><td><code>h2</code></td><td><code>h3</code></td></tr>"));
+        assert!(html.contains("<tr><td>a/&lt;moved&gt;.txt</td><td>c/&lt;moved&gt;.txt</td>"));
+        assert!(html.contains("<summary>c/ <span class=\"meta\">(1 files)</span></summary>"));
+        assert!(!html.contains("<moved>"));
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951_782_400 + 3723), "2000-02-29 01:02:03 UTC");
+        assert_eq!(format_time(1_767_225_599), "2025-12-31 23:59:59 UTC");
+    }
+}
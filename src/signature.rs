@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::entry::to_hex;
+use crate::index::{DIR_INDEX_FILENAME, HASH_INDEX_FILENAME, META_INDEX_FILENAME};
+
+pub const SIGNATURE_FILENAME: &str = ".auditr-sig";
+
+const SIGNED_FILENAMES: [&str; 3] = [HASH_INDEX_FILENAME, META_INDEX_FILENAME, DIR_INDEX_FILENAME];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Reads the secret key used for signing indexes from the given file.
+pub fn load_key(file_name: &Path) -> Result<Vec<u8>> {
+    let key = fs::read(file_name).
+        with_context(|| format!("Failed to read key file '{}'", file_name.display()))?;
+    if key.is_empty() {
+        bail!("Key file '{}' is empty", file_name.display());
+    }
+    Ok(key)
+}
+
+pub fn is_signed(path: &Path) -> bool {
+    path.join(SIGNATURE_FILENAME).exists()
+}
+
+/// Signs the index files of the given directory with HMAC-SHA256.
+pub fn sign(path: &Path, key: &[u8]) -> Result<()> {
+    let mac = compute_mac(path, key)?;
+    fs::write(path.join(SIGNATURE_FILENAME), format!("{}\n", to_hex(&mac.result().code())))?;
+    Ok(())
+}
+
+/// Verifies the signature of the index files of the given directory. Returns false if the index
+/// is not signed or the signature does not match.
+pub fn verify(path: &Path, key: &[u8]) -> Result<bool> {
+    if !is_signed(path) {
+        return Ok(false);
+    }
+
+    let signature = fs::read_to_string(path.join(SIGNATURE_FILENAME))?;
+    let signature = match from_hex(signature.trim()) {
+        Some(s) => s,
+        None => return Ok(false)
+    };
+
+    let mac = compute_mac(path, key)?;
+    Ok(mac.verify(&signature).is_ok())
+}
+
+fn compute_mac(path: &Path, key: &[u8]) -> Result<HmacSha256> {
+    let mut mac = HmacSha256::new_varkey(key).
+        map_err(|_| anyhow!("invalid key length"))?;
+
+    for file_name in SIGNED_FILENAMES.iter() {
+        let contents = fs::read(path.join(file_name))?;
+        mac.input(file_name.as_bytes());
+        mac.input(&(contents.len() as u64).to_le_bytes());
+        mac.input(&contents);
+    }
+
+    Ok(mac)
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes().
+        chunks(2).
+        map(|b| match b {
+            [_, _] => std::str::from_utf8(b).ok().and_then(|b| u8::from_str_radix(b, 16).ok()),
+            _ => None
+        }).
+        collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn given_index(path: &Path) -> Result<()> {
+        fs::write(path.join(HASH_INDEX_FILENAME), "abc  a.txt\n")?;
+        fs::write(path.join(META_INDEX_FILENAME), "1  2  a.txt\n")?;
+        fs::write(path.join(DIR_INDEX_FILENAME), "def  .\n")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_verify() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        given_index(temp.path())?;
+
+        // When
+        sign(temp.path(), b"secret")?;
+
+        // Then
+        assert!(is_signed(temp.path()));
+        assert!(verify(temp.path(), b"secret")?);
+        assert!(!verify(temp.path(), b"other secret")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_modified_index() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        given_index(temp.path())?;
+        sign(temp.path(), b"secret")?;
+
+        // When
+        fs::write(temp.path().join(META_INDEX_FILENAME), "1  3  a.txt\n")?;
+
+        // Then
+        assert!(!verify(temp.path(), b"secret")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_unsigned() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        given_index(temp.path())?;
+
+        // Then
+        assert!(!is_signed(temp.path()));
+        assert!(!verify(temp.path(), b"secret")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_invalid_signature() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        given_index(temp.path())?;
+        fs::write(temp.path().join(SIGNATURE_FILENAME), "no hex")?;
+
+        // Then
+        assert!(!verify(temp.path(), b"secret")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_key_empty() -> Result<()> {
+        // Given
+        let temp = tempdir()?;
+        fs::write(temp.path().join("key"), "")?;
+
+        // When
+        let result = load_key(&temp.path().join("key"));
+
+        // Then
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(from_hex("00ff1a"), Some(vec![0, 255, 26]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::iter::FromIterator;
-use std::path::PathBuf;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
 
 use crate::diff;
 use crate::diff::Event;
@@ -12,18 +14,45 @@ pub struct Stats<'a> {
     pub updated: Vec<&'a Entry>,
     pub updated_bitrot: Vec<&'a Entry>,
     pub moved: HashMap<PathBuf, &'a Entry>,
+    /// Files renamed to another Unicode normalization form of the same path.
+    pub renamed: HashMap<PathBuf, &'a Entry>,
+    /// New files with the same contents as an existing file of the index, together with that file.
+    pub copied: Vec<(&'a Entry, &'a Entry)>,
+    /// Removed files paired with added files of similar contents, together with their similarity
+    /// in percent.
+    pub moved_modified: Vec<(&'a Entry, &'a Entry, u32)>,
     pub unchanged: Vec<&'a Entry>,
     pub total: u64,
 }
 
+/// A directory whose files of the index were all moved to another directory.
+#[derive(Debug, PartialEq)]
+pub struct MovedDir {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Previous paths of the moved files.
+    pub files: Vec<PathBuf>,
+}
+
 impl<'a> Stats<'a> {
     pub fn modified(&self) -> bool {
         !self.added.is_empty() || !self.removed.is_empty() ||
-            !self.updated.is_empty() || !self.updated_bitrot.is_empty() || !self.moved.is_empty()
+            !self.updated.is_empty() || !self.updated_bitrot.is_empty() || !self.moved.is_empty() ||
+            !self.renamed.is_empty() || !self.copied.is_empty() || !self.moved_modified.is_empty()
+    }
+
+    /// Returns the number of bytes occupied by copies of existing files.
+    pub fn copied_bytes(&self) -> u64 {
+        self.copied.iter().map(|(copy, _)| copy.len).sum()
     }
 
     pub fn iter_new(&self) -> impl Iterator<Item=&'a Entry> {
-        let moved_entries: Vec<&'a Entry> = self.moved.values().copied().collect();
+        let moved_entries: Vec<&'a Entry> = self.moved.values().
+            chain(self.renamed.values()).
+            copied().
+            chain(self.copied.iter().map(|&(copy, _)| copy)).
+            chain(self.moved_modified.iter().map(|&(_, new, _)| new)).
+            collect();
         self.added.clone().into_iter().
             chain(self.unchanged.clone().into_iter()).
             chain(self.updated.clone().into_iter()).
@@ -31,35 +60,307 @@ impl<'a> Stats<'a> {
             chain(moved_entries)
     }
 
-    fn compute_moved(&mut self) {
+    /// Collapses moved files into moved directories. Moves are grouped by the directories left
+    /// over after stripping the longest common suffix of the old and new path. A group is reported
+    /// as a moved directory if it contains at least two files and all files of the index below the
+    /// source directory, otherwise its subdirectories are tried.
+    pub fn moved_dirs(&self) -> Vec<MovedDir> {
+        let old_paths = self.unchanged.iter().
+            chain(self.updated.iter()).
+            chain(self.updated_bitrot.iter()).
+            chain(self.removed.iter()).
+            map(|e| e.path.as_path()).
+            chain(self.moved.keys().map(|p| p.as_path())).
+            chain(self.renamed.keys().map(|p| p.as_path())).
+            chain(self.moved_modified.iter().map(|(old, _, _)| old.path.as_path())).
+            collect::<BTreeSet<&Path>>();
+
+        let mut groups: BTreeMap<(PathBuf, PathBuf), Vec<(PathBuf, &Path)>> = BTreeMap::new();
+        for (old, new) in self.moved.iter() {
+            let (from, to, suffix) = split_common_suffix(old, &new.path);
+            if suffix.as_os_str().is_empty() {
+                continue;
+            }
+            groups.entry((from, to)).or_default().push((suffix, old));
+        }
+
+        let mut dirs = Vec::new();
+        for ((from, to), files) in groups {
+            collapse_dir(&old_paths, from, to, files, &mut dirs);
+        }
+        dirs.sort_by(|a, b| a.from.cmp(&b.from));
+        dirs
+    }
+
+    /// Records the given pairs of removed and added files as moved and modified.
+    pub fn add_moved_modified(&mut self, pairs: Vec<(&'a Entry, &'a Entry, u32)>) {
+        let old = pairs.iter().map(|(r, _, _)| &r.path).collect::<HashSet<&PathBuf>>();
+        let new = pairs.iter().map(|(_, a, _)| &a.path).collect::<HashSet<&PathBuf>>();
+        self.removed.retain(|r| !old.contains(&r.path));
+        self.added.retain(|a| !new.contains(&a.path));
+        self.moved_modified.extend(pairs);
+    }
+
+    /// Pairs removed and added files whose paths only differ in their normalization form. With
+    /// byte-exact paths, such renames are detected as regular moves.
+    fn compute_renamed(&mut self) {
         let mut removed = self.removed.iter().
-            map(|&e| (e.hash.clone(), e)).
-            collect::<HashMap<String, &'a Entry>>();
+            map(|&e| (e.norm_path.as_str(), e)).
+            collect::<HashMap<&str, &'a Entry>>();
+
+        let same_contents = |old: &Entry, new: &Entry| Entry::compare_hash(old, new) ||
+            (new.hash.is_empty() && Entry::compare_meta(old, new));
 
         let mut added = Vec::new();
         for &a in &self.added {
-            match removed.remove_entry(&a.hash) {
-                Some((_, val)) => {
-                    self.moved.insert(val.path.clone(), a);
-                }
-                None => {
-                    added.push(a);
+            match removed.get(a.norm_path.as_str()) {
+                Some(&r) if r.path != a.path && same_contents(r, a) => {
+                    removed.remove(a.norm_path.as_str());
+                    self.renamed.insert(r.path.clone(), a);
                 }
+                _ => added.push(a)
             }
         }
 
-        let mut removed = Vec::new();
-        for &r in &self.removed {
-            if self.moved.get(&r.path).is_none() {
-                removed.push(r);
+        self.added = added;
+        let renamed = &self.renamed;
+        self.removed.retain(|r| !renamed.contains_key(&r.path));
+    }
+
+    /// Pairs removed and added files with the same hash. If several files share a hash, pairs with
+    /// the same file name are preferred, then pairs with the closest paths, so that the result
+    /// does not depend on the order of the files.
+    fn compute_moved(&mut self) {
+        let removed = group_by_hash(&self.removed);
+        let added = group_by_hash(&self.added);
+
+        for (hash, added_group) in added.iter() {
+            if let Some(removed_group) = removed.get(hash) {
+                for (r, a) in closest_pairs(removed_group, added_group) {
+                    self.moved.insert(r.path.clone(), a);
+                }
             }
         }
 
+        let moved = &self.moved;
+        let moved_to = moved.values().map(|e| &e.path).collect::<HashSet<&PathBuf>>();
+        self.added.retain(|a| !moved_to.contains(&a.path));
+        self.removed.retain(|r| !moved.contains_key(&r.path));
+    }
+
+    /// Detects added files with the same hash as a file of the index which still exists, i.e. an
+    /// unchanged or moved file.
+    fn compute_copied(&mut self) {
+        let existing = self.unchanged.iter().
+            chain(self.moved.values()).
+            chain(self.renamed.values()).
+            copied().
+            collect::<Vec<&Entry>>();
+        let existing = group_by_hash(&existing);
+        let added_by_hash = group_by_hash(&self.added);
+
+        // Large groups are looked up by name and directory instead of ranking every candidate
+        let mut lookups: HashMap<&str, NameLookup> = HashMap::new();
+        let mut original = |a: &'a Entry| {
+            let candidates = existing.get(a.hash.as_str())?;
+            if candidates.len().saturating_mul(added_by_hash[a.hash.as_str()].len()) <= MAX_RANKED_CANDIDATES {
+                closest(candidates, a)
+            } else {
+                lookups.entry(&a.hash).or_insert_with(|| NameLookup::new(candidates)).closest(a)
+            }
+        };
+
+        let mut added = Vec::new();
+        for &a in &self.added {
+            match original(a) {
+                Some(original) if !a.hash.is_empty() && a.len > 0 => self.copied.push((a, original)),
+                _ => added.push(a)
+            }
+        }
         self.added = added;
-        self.removed = removed
     }
 }
 
+fn group_by_hash<'a>(entries: &[&'a Entry]) -> HashMap<&'a str, Vec<&'a Entry>> {
+    let mut groups: HashMap<&str, Vec<&Entry>> = HashMap::new();
+    for &e in entries {
+        groups.entry(&e.hash).or_default().push(e);
+    }
+    groups
+}
+
+/// Maximum number of candidate pairs which are ranked at once. Larger groups of files with the
+/// same hash (e.g. thousands of empty files) are paired in path order instead.
+const MAX_RANKED_CANDIDATES: usize = 100_000;
+
+/// Ranks how well two files match: files with the same name rank first, then files whose paths
+/// are closer in the directory tree. Ties are broken by the paths.
+fn rank<'a>(a: &'a Entry, b: &'a Entry) -> (bool, usize, &'a Path, &'a Path) {
+    let a_dir = a.path.parent().unwrap_or_else(|| Path::new(""));
+    let b_dir = b.path.parent().unwrap_or_else(|| Path::new(""));
+    let common = a_dir.components().zip(b_dir.components()).take_while(|(x, y)| x == y).count();
+    let distance = a_dir.components().count() + b_dir.components().count() - 2 * common;
+    (a.path.file_name() != b.path.file_name(), distance, &a.path, &b.path)
+}
+
+/// Pairs the given files by their rank, each file is part of at most one pair. The cheap cases are
+/// paired first: files with the same path, then files with the same name, then files in the same
+/// directory. Only the remaining files are ranked against each other.
+fn closest_pairs<'a>(old: &[&'a Entry], new: &[&'a Entry]) -> Vec<(&'a Entry, &'a Entry)> {
+    let mut old = old.to_vec();
+    let mut new = new.to_vec();
+    let mut pairs = Vec::new();
+
+    pair_by(&mut old, &mut new, &mut pairs, |e| Some(e.path.as_path()));
+    pair_by(&mut old, &mut new, &mut pairs, |e| e.path.file_name().map(Path::new));
+    pair_by(&mut old, &mut new, &mut pairs, |e| e.path.parent());
+    pairs.extend(ranked_pairs(&old, &new));
+    pairs
+}
+
+/// Pairs files with the same key and removes them from the given files.
+fn pair_by<'a, F>(old: &mut Vec<&'a Entry>, new: &mut Vec<&'a Entry>, pairs: &mut Vec<(&'a Entry, &'a Entry)>, key: F) where
+    F: Fn(&'a Entry) -> Option<&'a Path> {
+    let mut groups: HashMap<&Path, (Vec<&Entry>, Vec<&Entry>)> = HashMap::new();
+    for &o in old.iter() {
+        if let Some(k) = key(o) {
+            groups.entry(k).or_default().0.push(o);
+        }
+    }
+    for &n in new.iter() {
+        if let Some(group) = key(n).and_then(|k| groups.get_mut(k)) {
+            group.1.push(n);
+        }
+    }
+
+    let mut used_old = HashSet::new();
+    let mut used_new = HashSet::new();
+    for (group_old, group_new) in groups.values().filter(|(_, n)| !n.is_empty()) {
+        for (o, n) in ranked_pairs(group_old, group_new) {
+            used_old.insert(&o.path);
+            used_new.insert(&n.path);
+            pairs.push((o, n));
+        }
+    }
+
+    old.retain(|o| !used_old.contains(&o.path));
+    new.retain(|n| !used_new.contains(&n.path));
+}
+
+/// Pairs the given files greedily by their rank. If there are too many candidates to rank, the
+/// files are paired in path order.
+fn ranked_pairs<'a>(old: &[&'a Entry], new: &[&'a Entry]) -> Vec<(&'a Entry, &'a Entry)> {
+    if old.len().saturating_mul(new.len()) > MAX_RANKED_CANDIDATES {
+        let mut old = old.to_vec();
+        let mut new = new.to_vec();
+        old.sort_unstable();
+        new.sort_unstable();
+        return old.into_iter().zip(new).collect();
+    }
+
+    let mut candidates = old.iter().
+        flat_map(|&o| new.iter().map(move |&n| (rank(o, n), o, n))).
+        collect::<Vec<_>>();
+    candidates.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut used_old = HashSet::new();
+    let mut used_new = HashSet::new();
+    let mut pairs = Vec::new();
+    for (_, o, n) in candidates {
+        if !used_old.contains(&o.path) && !used_new.contains(&n.path) {
+            used_old.insert(&o.path);
+            used_new.insert(&n.path);
+            pairs.push((o, n));
+        }
+    }
+    pairs
+}
+
+/// Splits both paths into the leading directories and their longest common suffix.
+fn split_common_suffix(a: &Path, b: &Path) -> (PathBuf, PathBuf, PathBuf) {
+    let a = a.components().collect::<Vec<_>>();
+    let b = b.components().collect::<Vec<_>>();
+    let common = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    (a[..a.len() - common].iter().collect(),
+     b[..b.len() - common].iter().collect(),
+     a[a.len() - common..].iter().collect())
+}
+
+/// Reports the files moved from `from` to `to` (given by their path relative to `from` and their
+/// old path) as a moved directory if possible, otherwise tries each subdirectory.
+fn collapse_dir(old_paths: &BTreeSet<&Path>, from: PathBuf, to: PathBuf, files: Vec<(PathBuf, &Path)>, dirs: &mut Vec<MovedDir>) {
+    if !from.as_os_str().is_empty() && !to.as_os_str().is_empty() {
+        let below = old_paths.range::<&Path, _>((Bound::Excluded(from.as_path()), Bound::Unbounded)).
+            take_while(|p| p.starts_with(&from)).
+            count();
+        if files.len() >= 2 && below == files.len() {
+            let mut files = files.into_iter().map(|(_, old)| old.to_path_buf()).collect::<Vec<PathBuf>>();
+            files.sort();
+            dirs.push(MovedDir {
+                from,
+                to,
+                files,
+            });
+            return;
+        }
+    }
+
+    let mut subdirs: BTreeMap<OsString, Vec<(PathBuf, &Path)>> = BTreeMap::new();
+    for (suffix, old) in files {
+        let mut components = suffix.components();
+        if let Some(first) = components.next() {
+            if !components.as_path().as_os_str().is_empty() {
+                subdirs.entry(first.as_os_str().to_owned()).or_default().push((components.as_path().to_path_buf(), old));
+            }
+        }
+    }
+    for (name, files) in subdirs {
+        collapse_dir(old_paths, from.join(&name), to.join(&name), files, dirs);
+    }
+}
+
+/// Finds the closest of many files without ranking all of them: a file with the same name is
+/// preferred, then a file in the same directory, otherwise the first file in path order.
+struct NameLookup<'a> {
+    by_name: HashMap<&'a OsStr, &'a Entry>,
+    by_dir: HashMap<&'a Path, &'a Entry>,
+    first: Option<&'a Entry>,
+}
+
+impl<'a> NameLookup<'a> {
+    fn new(candidates: &[&'a Entry]) -> NameLookup<'a> {
+        let mut candidates = candidates.to_vec();
+        candidates.sort_unstable();
+
+        let mut lookup = NameLookup {
+            by_name: HashMap::new(),
+            by_dir: HashMap::new(),
+            first: candidates.first().copied(),
+        };
+        for e in candidates {
+            if let Some(name) = e.path.file_name() {
+                lookup.by_name.entry(name).or_insert(e);
+            }
+            lookup.by_dir.entry(e.path.parent().unwrap_or_else(|| Path::new(""))).or_insert(e);
+        }
+        lookup
+    }
+
+    fn closest(&self, e: &Entry) -> Option<&'a Entry> {
+        e.path.file_name().and_then(|name| self.by_name.get(name)).
+            or_else(|| self.by_dir.get(e.path.parent().unwrap_or_else(|| Path::new("")))).
+            copied().
+            or(self.first)
+    }
+}
+
+/// Returns the file closest to the given file.
+fn closest<'a>(candidates: &[&'a Entry], e: &'a Entry) -> Option<&'a Entry> {
+    candidates.iter().
+        copied().
+        min_by(|a, b| rank(a, e).cmp(&rank(b, e)))
+}
+
 impl<'a> FromIterator<Event<'a, Entry>> for Stats<'a> {
     fn from_iter<T: IntoIterator<Item=Event<'a, Entry>>>(iter: T) -> Self {
         let mut stats = Stats {
@@ -69,6 +370,9 @@ impl<'a> FromIterator<Event<'a, Entry>> for Stats<'a> {
             updated_bitrot: Vec::new(),
             unchanged: Vec::new(),
             moved: HashMap::new(),
+            renamed: HashMap::new(),
+            copied: Vec::new(),
+            moved_modified: Vec::new(),
             total: 0,
         };
 
@@ -97,7 +401,9 @@ impl<'a> FromIterator<Event<'a, Entry>> for Stats<'a> {
             }
         }
 
+        stats.compute_renamed();
         stats.compute_moved();
+        stats.compute_copied();
 
         stats
     }
@@ -131,6 +437,9 @@ mod tests {
             updated: vec![],
             updated_bitrot: vec![],
             moved: Default::default(),
+            renamed: Default::default(),
+            copied: vec![],
+            moved_modified: vec![],
             unchanged: vec![],
             total: 0,
         };
@@ -153,6 +462,9 @@ mod tests {
             updated: vec![],
             updated_bitrot: vec![],
             moved: Default::default(),
+            renamed: Default::default(),
+            copied: vec![],
+            moved_modified: vec![],
             unchanged: vec![&entry],
             total: 1,
         };
@@ -175,6 +487,9 @@ mod tests {
             updated: vec![],
             updated_bitrot: vec![],
             moved: Default::default(),
+            renamed: Default::default(),
+            copied: vec![],
+            moved_modified: vec![],
             unchanged: vec![],
             total: 1,
         };
@@ -197,6 +512,9 @@ mod tests {
             updated: vec![],
             updated_bitrot: vec![],
             moved: Default::default(),
+            renamed: Default::default(),
+            copied: vec![],
+            moved_modified: vec![],
             unchanged: vec![],
             total: 1,
         };
@@ -219,6 +537,9 @@ mod tests {
             updated: vec![&entry],
             updated_bitrot: vec![],
             moved: Default::default(),
+            renamed: Default::default(),
+            copied: vec![],
+            moved_modified: vec![],
             unchanged: vec![],
             total: 1,
         };
@@ -241,6 +562,9 @@ mod tests {
             updated: vec![],
             updated_bitrot: vec![&entry],
             moved: Default::default(),
+            renamed: Default::default(),
+            copied: vec![],
+            moved_modified: vec![],
             unchanged: vec![],
             total: 1,
         };
@@ -266,6 +590,9 @@ mod tests {
             updated: vec![],
             updated_bitrot: vec![],
             moved: moved_files,
+            renamed: Default::default(),
+            copied: vec![],
+            moved_modified: vec![],
             unchanged: vec![],
             total: 1,
         };
@@ -296,6 +623,9 @@ mod tests {
             updated: vec![&updated_entry],
             updated_bitrot: vec![&bitrot_entry],
             moved: moved_files,
+            renamed: Default::default(),
+            copied: vec![],
+            moved_modified: vec![],
             unchanged: vec![&unchanged_entry],
             total: 6,
         };
@@ -487,9 +817,260 @@ mod tests {
         assert_eq!(stats.moved.len(), 2);
         assert_eq!(stats.moved.get(moved_entry_1_from.path.as_path()), Some(&&moved_entry_1a_to));
         assert_eq!(stats.moved.get(moved_entry_2a_from.path.as_path()), Some(&&moved_entry_2_to));
-        assert_eq!(stats.added, vec![&moved_entry_1b_to]);
+        assert_eq!(stats.copied, vec![(&moved_entry_1b_to, &moved_entry_1a_to)]);
+        assert!(stats.added.is_empty());
         assert_eq!(stats.removed, vec![&moved_entry_2b_from]);
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_from_iter_renamed() -> Result<()> {
+        // Given
+        let nfc_entry = Entry {
+            path: PathBuf::from("caf\u{e9}.txt"),
+            ..given_entry("caf\u{e9}.txt")
+        };
+        let nfd_entry = Entry {
+            path: PathBuf::from("cafe\u{301}.txt"),
+            ..given_entry("caf\u{e9}.txt")
+        };
+        let updated_entry = Entry {
+            hash: String::from("other hash"),
+            ..nfd_entry.clone()
+        };
+
+        // When
+        let stats = Stats::from_iter(vec![
+            Event::ADDED { new: &nfc_entry },
+            Event::REMOVED { old: &nfd_entry },
+        ]);
+
+        // Then
+        assert_eq!(stats.renamed.get(nfd_entry.path.as_path()), Some(&&nfc_entry));
+        assert!(stats.moved.is_empty());
+        assert!(stats.added.is_empty());
+        assert!(stats.removed.is_empty());
+        assert!(stats.modified());
+
+        let stats = Stats::from_iter(vec![
+            Event::ADDED { new: &nfc_entry },
+            Event::REMOVED { old: &updated_entry },
+        ]);
+        assert!(stats.renamed.is_empty());
+        assert_eq!(stats.added, vec![&nfc_entry]);
+        assert_eq!(stats.removed, vec![&updated_entry]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_iter_moved_many_to_many() -> Result<()> {
+        // Given
+        let entry = |name: &str| Entry {
+            hash: String::from("same hash"),
+            ..given_entry(name)
+        };
+        let removed_a = entry("a/photo.jpg");
+        let removed_b = entry("b/empty.txt");
+        let removed_c = entry("c/empty.txt");
+        let added_a = entry("x/photo.jpg");
+        let added_b = entry("b/sub/other.txt");
+        let added_c = entry("y/empty.txt");
+
+        // When
+        let stats = Stats::from_iter(vec![
+            Event::REMOVED { old: &removed_a },
+            Event::REMOVED { old: &removed_b },
+            Event::REMOVED { old: &removed_c },
+            Event::ADDED { new: &added_a },
+            Event::ADDED { new: &added_b },
+            Event::ADDED { new: &added_c },
+        ]);
+
+        // Then
+        assert_eq!(stats.moved.len(), 3);
+        assert_eq!(stats.moved.get(removed_a.path.as_path()), Some(&&added_a));
+        assert_eq!(stats.moved.get(removed_b.path.as_path()), Some(&&added_c));
+        assert_eq!(stats.moved.get(removed_c.path.as_path()), Some(&&added_b));
+        assert!(stats.added.is_empty());
+        assert!(stats.removed.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_iter_moved_large_hash_group() -> Result<()> {
+        // Given
+        let entry = |name: String| Entry {
+            hash: String::from("empty"),
+            ..given_entry(&name)
+        };
+        let removed = (0..5000).map(|i| entry(format!("a/{}/.gitkeep", i))).collect::<Vec<Entry>>();
+        let added = (0..5000).map(|i| entry(format!("b/{}/.gitkeep", i))).
+            chain((0..5000).map(|i| entry(format!("c/{}.txt", i)))).
+            collect::<Vec<Entry>>();
+
+        // When
+        let stats = Stats::from_iter(removed.iter().map(|old| Event::REMOVED { old }).
+            chain(added.iter().map(|new| Event::ADDED { new })));
+
+        // Then
+        assert_eq!(stats.moved.len(), 5000);
+        assert_eq!(stats.moved[Path::new("a/42/.gitkeep")].path, PathBuf::from("b/42/.gitkeep"));
+        assert!(stats.removed.is_empty());
+        assert_eq!(stats.added.len() + stats.copied.len(), 5000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_iter_copied() -> Result<()> {
+        // Given
+        let original = given_entry("a/original.txt");
+        let other = Entry {
+            hash: original.hash.clone(),
+            ..given_entry("b/original.txt")
+        };
+        let copy = Entry {
+            hash: original.hash.clone(),
+            ..given_entry("b/copy/original.txt")
+        };
+        let added = given_entry("added.txt");
+
+        // When
+        let stats = Stats::from_iter(vec![
+            Event::UNCHANGED { old: &original, new: &original },
+            Event::UNCHANGED { old: &other, new: &other },
+            Event::ADDED { new: &copy },
+            Event::ADDED { new: &added },
+        ]);
+
+        // Then
+        assert_eq!(stats.copied, vec![(&copy, &other)]);
+        assert_eq!(stats.added, vec![&added]);
+        assert_eq!(stats.copied_bytes(), 123);
+        assert_eq!(stats.total, 4);
+        assert!(stats.modified());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_iter_copied_empty() -> Result<()> {
+        // Given
+        let original = Entry {
+            len: 0,
+            ..given_entry("a/empty.txt")
+        };
+        let copy = Entry {
+            hash: original.hash.clone(),
+            len: 0,
+            ..given_entry("b/empty.txt")
+        };
+
+        // When
+        let stats = Stats::from_iter(vec![
+            Event::UNCHANGED { old: &original, new: &original },
+            Event::ADDED { new: &copy },
+        ]);
+
+        // Then
+        assert!(stats.copied.is_empty());
+        assert_eq!(stats.added, vec![&copy]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_moved_dirs() -> Result<()> {
+        // Given
+        let old = ["projects/acme/a.txt", "projects/acme/sub/b.txt", "projects/other/c.txt", "projects/other/d.txt", "e.txt"].
+            iter().
+            map(|p| given_entry(p)).
+            collect::<Vec<Entry>>();
+        let new = ["archive/acme/a.txt", "archive/acme/sub/b.txt", "archive/other/c.txt", "projects/moved/d.txt", "f/e.txt"].
+            iter().
+            zip(old.iter()).
+            map(|(p, o)| Entry {
+                hash: o.hash.clone(),
+                ..given_entry(p)
+            }).
+            collect::<Vec<Entry>>();
+        let unchanged = given_entry("projects/other/unchanged.txt");
+
+        // When
+        let mut events = old.iter().
+            map(|o| Event::REMOVED { old: o }).
+            chain(new.iter().map(|n| Event::ADDED { new: n })).
+            collect::<Vec<_>>();
+        events.push(Event::UNCHANGED { old: &unchanged, new: &unchanged });
+        let stats = Stats::from_iter(events);
+
+        // Then
+        assert_eq!(stats.moved.len(), 5);
+        assert_eq!(stats.moved_dirs(), vec![
+            MovedDir {
+                from: PathBuf::from("projects/acme"),
+                to: PathBuf::from("archive/acme"),
+                files: vec![PathBuf::from("projects/acme/a.txt"), PathBuf::from("projects/acme/sub/b.txt")],
+            },
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_moved_modified() -> Result<()> {
+        // Given
+        let old = given_entry("a/old.txt");
+        let new = given_entry("b/new.txt");
+        let added = given_entry("added.txt");
+        let mut stats = Stats::from_iter(vec![
+            Event::REMOVED { old: &old },
+            Event::ADDED { new: &new },
+            Event::ADDED { new: &added },
+        ]);
+
+        // When
+        stats.add_moved_modified(vec![(&old, &new, 87)]);
+
+        // Then
+        assert_eq!(stats.moved_modified, vec![(&old, &new, 87)]);
+        assert_eq!(stats.added, vec![&added]);
+        assert!(stats.removed.is_empty());
+        assert_eq!(stats.iter_new().collect::<Vec<&Entry>>(), vec![&added, &new]);
+        assert!(stats.modified());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_iter_copied_moved() -> Result<()> {
+        // Given
+        let original = given_entry("a.txt");
+        let moved = Entry {
+            hash: original.hash.clone(),
+            ..given_entry("b/a.txt")
+        };
+        let copy = Entry {
+            hash: original.hash.clone(),
+            ..given_entry("c/a.txt")
+        };
+
+        // When
+        let stats = Stats::from_iter(vec![
+            Event::REMOVED { old: &original },
+            Event::ADDED { new: &moved },
+            Event::ADDED { new: &copy },
+        ]);
+
+        // Then
+        assert_eq!(stats.moved.len(), 1);
+        assert_eq!(stats.copied.len(), 1);
+        assert!(stats.added.is_empty());
+        assert!(stats.removed.is_empty());
+
+        Ok(())
+    }
+}
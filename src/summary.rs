@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::bail;
+
+use crate::entry::Entry;
+use crate::stats::Stats;
+
+/// Changes below a directory of the tree.
+#[derive(Debug, Default, PartialEq)]
+pub struct DirSummary {
+    pub dir: PathBuf,
+    pub added: u64,
+    pub removed: u64,
+    /// Updated files, including files with bitrot.
+    pub updated: u64,
+    /// Files moved into or out of the directory.
+    pub moved: u64,
+    /// Net change of the size of all files in bytes.
+    pub bytes: i64,
+}
+
+impl DirSummary {
+    pub fn changes(&self) -> u64 {
+        self.added + self.removed + self.updated + self.moved
+    }
+}
+
+/// Order of the directories of a summary.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Path,
+    /// Most changed files first.
+    Changes,
+    /// Largest absolute byte change first.
+    Bytes,
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(SortOrder::Path),
+            "changes" => Ok(SortOrder::Changes),
+            "bytes" => Ok(SortOrder::Bytes),
+            _ => bail!("invalid sort order '{}', expected path, changes or bytes", s)
+        }
+    }
+}
+
+/// Aggregates the changes of the given stats per directory, using the first `depth` directories
+/// of each path. Files closer to the root are aggregated in their parent directory, files in the
+/// root itself in `.`. The previous sizes of updated files are looked up in the given index
+/// entries, which must be sorted.
+pub fn summarize(stats: &Stats, entries: &[Entry], depth: usize, order: SortOrder) -> Vec<DirSummary> {
+    let mut dirs: HashMap<PathBuf, DirSummary> = HashMap::new();
+
+    for e in stats.added.iter().chain(stats.copied.iter().map(|(copy, _)| copy)) {
+        let d = summary_of(&mut dirs, &e.path, depth);
+        d.added += 1;
+        d.bytes += e.len as i64;
+    }
+    for e in stats.removed.iter() {
+        let d = summary_of(&mut dirs, &e.path, depth);
+        d.removed += 1;
+        d.bytes -= e.len as i64;
+    }
+    for e in stats.updated.iter().chain(stats.updated_bitrot.iter()) {
+        let old_len = entries.binary_search(e).map(|i| entries[i].len).unwrap_or(e.len);
+        let d = summary_of(&mut dirs, &e.path, depth);
+        d.updated += 1;
+        d.bytes += e.len as i64 - old_len as i64;
+    }
+
+    let moves = stats.moved.iter().
+        chain(stats.renamed.iter()).
+        map(|(old, new)| (old.as_path(), new.len, *new)).
+        chain(stats.moved_modified.iter().map(|(old, new, _)| (old.path.as_path(), old.len, *new)));
+    for (old, old_len, new) in moves {
+        let from = dir_at_depth(old, depth);
+        let to = dir_at_depth(&new.path, depth);
+        if from == to {
+            let d = summary_of(&mut dirs, &new.path, depth);
+            d.moved += 1;
+            d.bytes += new.len as i64 - old_len as i64;
+        } else {
+            let d = summary_of(&mut dirs, old, depth);
+            d.moved += 1;
+            d.bytes -= old_len as i64;
+            let d = summary_of(&mut dirs, &new.path, depth);
+            d.moved += 1;
+            d.bytes += new.len as i64;
+        }
+    }
+
+    let mut summary = dirs.into_values().collect::<Vec<DirSummary>>();
+    match order {
+        SortOrder::Path => summary.sort_by(|a, b| a.dir.cmp(&b.dir)),
+        SortOrder::Changes => summary.sort_by(|a, b| b.changes().cmp(&a.changes()).
+            then_with(|| a.dir.cmp(&b.dir))),
+        SortOrder::Bytes => summary.sort_by(|a, b| b.bytes.abs().cmp(&a.bytes.abs()).
+            then_with(|| a.dir.cmp(&b.dir))),
+    }
+    summary
+}
+
+fn summary_of<'a>(dirs: &'a mut HashMap<PathBuf, DirSummary>, path: &Path, depth: usize) -> &'a mut DirSummary {
+    let dir = dir_at_depth(path, depth);
+    dirs.entry(dir.clone()).or_insert_with(|| DirSummary {
+        dir,
+        ..DirSummary::default()
+    })
+}
+
+fn dir_at_depth(path: &Path, depth: usize) -> PathBuf {
+    let dir = path.parent().
+        map(|p| p.components().take(depth).collect::<PathBuf>()).
+        unwrap_or_default();
+    if dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::FromIterator;
+
+    use crate::diff::Event;
+    use crate::entry::given_entry;
+
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        // Given
+        let old = vec![
+            given_entry("a/b/moved.txt", "h1", 100, 123),
+            given_entry("a/removed.txt", "h2", 10, 123),
+            given_entry("c/updated.txt", "h3", 50, 123),
+            given_entry("root.txt", "h4", 5, 123),
+        ];
+        let moved = given_entry("c/d/moved.txt", "h1", 100, 123);
+        let updated = given_entry("c/updated.txt", "h5", 80, 123);
+        let added = given_entry("c/d/e/added.txt", "h6", 1000, 123);
+        let stats = Stats::from_iter(vec![
+            Event::REMOVED { old: &old[0] },
+            Event::REMOVED { old: &old[1] },
+            Event::UPDATED { old: &old[2], new: &updated },
+            Event::UNCHANGED { old: &old[3], new: &old[3] },
+            Event::ADDED { new: &moved },
+            Event::ADDED { new: &added },
+        ]);
+
+        // When
+        let summary = summarize(&stats, &old, 1, SortOrder::Path);
+
+        // Then
+        assert_eq!(summary, vec![
+            DirSummary {
+                dir: PathBuf::from("a"),
+                removed: 1,
+                moved: 1,
+                bytes: -110,
+                ..DirSummary::default()
+            },
+            DirSummary {
+                dir: PathBuf::from("c"),
+                added: 1,
+                updated: 1,
+                moved: 1,
+                bytes: 1130,
+                ..DirSummary::default()
+            },
+        ]);
+
+        let summary = summarize(&stats, &old, 2, SortOrder::Bytes);
+        assert_eq!(summary.iter().map(|s| s.dir.to_str().unwrap()).collect::<Vec<&str>>(),
+                   vec!["c/d", "a/b", "c", "a"]);
+
+        let summary = summarize(&stats, &old, 2, SortOrder::Changes);
+        assert_eq!(summary.iter().map(|s| s.dir.to_str().unwrap()).collect::<Vec<&str>>(),
+                   vec!["c/d", "a", "a/b", "c"]);
+    }
+
+    #[test]
+    fn test_dir_at_depth() {
+        assert_eq!(dir_at_depth(Path::new("a.txt"), 1), PathBuf::from("."));
+        assert_eq!(dir_at_depth(Path::new("a/b/c.txt"), 1), PathBuf::from("a"));
+        assert_eq!(dir_at_depth(Path::new("a/b/c.txt"), 3), PathBuf::from("a/b"));
+    }
+}
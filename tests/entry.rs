@@ -1,12 +1,12 @@
 extern crate auditr;
 
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 use tempfile::tempdir;
 
-use auditr::entry::Entry;
+use auditr::entry::{collisions, normalization_form, normalize, Entry, Normalization};
 pub use common::*;
 
 mod common;
@@ -185,3 +185,41 @@ fn test_update_hash_non_existing_file() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_normalize() -> Result<()> {
+    // Given
+    let mut entries = vec![
+        Entry::from_path(Path::new("cafe\u{301}.txt")),
+        Entry::from_path(Path::new("b.txt")),
+        Entry::from_path(Path::new("caf\u{e9}.txt")),
+    ];
+
+    // When
+    normalize(&mut entries, Normalization::Nfd);
+
+    // Then
+    assert_eq!(entries[0].norm_path, "b.txt");
+    assert_eq!(entries[1].norm_path, "cafe\u{301}.txt");
+    assert_eq!(entries[2].norm_path, "cafe\u{301}.txt");
+    assert_ne!(entries[1], entries[2]);
+    assert_eq!(collisions(&entries).len(), 1);
+
+    normalize(&mut entries, Normalization::None);
+    assert_eq!(entries[1].norm_path, "cafe\u{301}.txt");
+    assert_eq!(entries[2].norm_path, "caf\u{e9}.txt");
+    assert!(collisions(&entries).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_normalization_form() -> Result<()> {
+    assert_eq!(normalization_form(Path::new("a.txt")), "NFC");
+    assert_eq!(normalization_form(Path::new("caf\u{e9}.txt")), "NFC");
+    assert_eq!(normalization_form(Path::new("cafe\u{301}.txt")), "NFD");
+    assert!("nfx".parse::<Normalization>().is_err());
+    assert_eq!("none".parse::<Normalization>()?, Normalization::None);
+
+    Ok(())
+}
@@ -7,14 +7,19 @@ use anyhow::{bail, Result};
 use indoc::indoc;
 use tempfile::tempdir;
 
+use auditr::filter::gitfilter::GIT_FILTER_FILENAME;
 use auditr::filter::globfilter::GLOB_FILTER_FILENAME;
-use auditr::index::{HASH_INDEX_FILENAME, META_INDEX_FILENAME};
+use auditr::index::{DIR_INDEX_FILENAME, HASH_INDEX_FILENAME, META_INDEX_FILENAME};
+use auditr::journal::JOURNAL_FILENAME;
 pub use common::*;
 
 mod common;
 
 const BINARY_PATH: &str = "target/debug/auditr";
 
+/// Home directory of the tests, which does not exist so that no user configuration applies.
+const MISSING_HOME: &str = "/nonexistent/auditr-home";
+
 #[test]
 fn test_init() -> Result<()> {
     // Given
@@ -32,6 +37,29 @@ fn test_init() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_init_root_hash() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let replica = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    given_dir_without_index(replica.path())?;
+
+    // When
+    let result = run_init(temp.path())?;
+    let replica_result = run_init(replica.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+    assert!(temp.path().join(DIR_INDEX_FILENAME).exists());
+
+    let root_hash = |out: &str| out.lines().find(|l| l.starts_with("Root hash: ")).map(String::from);
+    assert!(root_hash(&stdout(&result)).is_some());
+    assert_eq!(root_hash(&stdout(&result)), root_hash(&stdout(&replica_result)));
+
+    Ok(())
+}
+
 #[test]
 fn test_init_twice() -> Result<()> {
     // Given
@@ -172,6 +200,58 @@ fn test_audit_update_bitrot() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_audit_signed_index() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let key = tempdir()?;
+    let key_file = key.path().join("key");
+    given_file_with_contents(key.path(), "key", "secret")?;
+    given_dir_without_index(temp.path())?;
+    let result = run_with_key("init", temp.path(), &key_file)?;
+    assert_eq!(status_code(&result), 0);
+
+    // When
+    let result = run_with_key("audit", temp.path(), &key_file)?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let result = run_audit(temp.path())?;
+    assert_eq!(status_code(&result), 0);
+    assert!(stderr(&result).contains("the index is signed, but no key was given"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_tampered_index() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let key = tempdir()?;
+    let key_file = key.path().join("key");
+    given_file_with_contents(key.path(), "key", "secret")?;
+    given_dir_without_index(temp.path())?;
+    let result = run_with_key("init", temp.path(), &key_file)?;
+    assert_eq!(status_code(&result), 0);
+
+    // Modify a file and regenerate the index without key
+    replace_file_with_contents(temp.path(), "a/f2a.txt", "f3", true)?;
+    std::fs::remove_file(temp.path().join(HASH_INDEX_FILENAME))?;
+    std::fs::remove_file(temp.path().join(META_INDEX_FILENAME))?;
+    let result = run_init(temp.path())?;
+    assert_eq!(status_code(&result), 0);
+
+    // When
+    let result = run_with_key("audit", temp.path(), &key_file)?;
+
+    // Then
+    assert_eq!(status_code(&result), 1);
+    assert!(stderr(&result).contains("Index signature is missing or invalid"));
+
+    Ok(())
+}
+
 #[test]
 fn test_audit_without_index() -> Result<()> {
     // Given
@@ -294,115 +374,1342 @@ fn test_filter_init_audit() -> Result<()> {
 }
 
 #[test]
-fn test_filter_update() -> Result<()> {
+fn test_filter_gitignore() -> Result<()> {
     // Given
     let temp = tempdir()?;
-    let path = temp.path();
-    // let path = Path::new("/tmp/test");
-    given_file_with_contents(path, GLOB_FILTER_FILENAME, indoc!("
-        !a/b*
-        a/**
+
+    given_file_with_contents(temp.path(), GIT_FILTER_FILENAME, indoc!("
+        b/
+        large.txt
     "))?;
-    given_dir_with_modified_index(path, true)?;
+    given_dir_with_index(temp.path())?;
 
     // When
-    let result = run_update(temp.path(), true)?;
+    let result = run_audit(temp.path())?;
 
     // Then
     assert_eq!(status_code(&result), 0);
 
     let out = stdout(&result);
-    assert!(match_regex(&out, r"(?m)^Updated:\s+1$"));
-    assert!(match_regex(&out, r"(?m)^Removed:\s+2$"));
-    assert!(match_regex(&out, r"(?m)^Unchanged:\s+1$"));
-    assert!(match_regex(&out, r"(?m)^Total:\s+2$"));
+    assert!(match_regex(&out, r"(?m)^Unchanged:\s+4$"));
+    assert!(match_regex(&out, r"(?m)^Total:\s+4$"));
 
-    assert!(out.contains("[*] f1.txt"));
-    assert!(out.contains("[-] a/b/f3.txt"));
-    assert!(out.contains("[-] c/large.txt"));
+    Ok(())
+}
+
+#[test]
+fn test_filter_nested() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+
+    given_file_with_contents(temp.path(), &format!("c/{}", GLOB_FILTER_FILENAME), "large.txt\n")?;
+    given_dir_with_index(temp.path())?;
+
+    // When
+    let result = run_audit(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    // The nested ignore file itself is indexed
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Unchanged:\s+6$"));
+    assert!(match_regex(&out, r"(?m)^Total:\s+6$"));
 
     Ok(())
 }
 
-fn run_init(base: &Path) -> io::Result<Output> {
-    let path = base.to_string_lossy();
-    Command::new(BINARY_PATH).
+#[test]
+fn test_filter_respect_gitignore() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+
+    given_file_with_contents(temp.path(), ".gitignore", "c/\n")?;
+    given_file_with_contents(temp.path(), "target/debug/out", "out")?;
+    given_dir_without_index(temp.path())?;
+    run_with_flag("init", "--respect-gitignore", temp.path())?;
+
+    // When
+    let result = run_with_flag("audit", "--respect-gitignore", temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Unchanged:\s+5$"));
+    assert!(match_regex(&out, r"(?m)^Total:\s+5$"));
+
+    Ok(())
+}
+
+#[test]
+fn test_filter_exclude_include() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+
+    // When
+    let result = auditr().
         arg("init").
-        arg(path.as_ref()).
-        output()
+        args(["--exclude", "a/*", "--exclude", "c/*", "--include", "a/f2a.txt"]).
+        arg(temp.path().to_string_lossy().as_ref()).
+        output()?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let index = std::fs::read_to_string(temp.path().join(HASH_INDEX_FILENAME))?;
+    assert_eq!(index.lines().count(), 2);
+    assert!(index.contains("  f1.txt"));
+    assert!(index.contains("  a/f2a.txt"));
+
+    Ok(())
 }
 
-fn run_audit(base: &Path) -> io::Result<Output> {
-    let path = base.to_string_lossy();
-    Command::new(BINARY_PATH).
-        arg("audit").
-        arg(path.as_ref()).
-        output()
+#[test]
+fn test_filter_size() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+
+    given_file_with_contents(temp.path(), GLOB_FILTER_FILENAME, "size>5K\n")?;
+    given_dir_with_index(temp.path())?;
+
+    // When
+    let result = run_audit(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Unchanged:\s+4$"));
+    assert!(match_regex(&out, r"(?m)^Total:\s+4$"));
+
+    Ok(())
 }
 
-fn run_audit_update(base: &Path) -> io::Result<Output> {
-    let path = base.to_string_lossy();
-    Command::new(BINARY_PATH).
-        arg("audit").
-        arg("--update").
-        arg(path.as_ref()).
-        output()
+#[test]
+fn test_filter_regex() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+
+    given_file_with_contents(temp.path(), GLOB_FILTER_FILENAME, "re:^c/\n")?;
+    given_dir_with_index(temp.path())?;
+
+    // When
+    let result = run_audit(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Total:\s+4$"));
+
+    Ok(())
 }
 
-fn run_update(base: &Path, cont: bool) -> Result<Output> {
-    let path = base.to_string_lossy();
-    let mut c = Command::new(BINARY_PATH).
-        arg("update").
-        arg(path.as_ref()).
-        stdin(Stdio::piped()).
-        stdout(Stdio::piped()).
-        stderr(Stdio::piped()).
-        spawn()?;
+#[test]
+fn test_filter_invalid_rule() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    given_file_with_contents(temp.path(), GLOB_FILTER_FILENAME, "*.log\nre:(unclosed\n")?;
 
-    if let Some(ref mut stdin) = c.stdin {
-        let mut writer = BufWriter::new(stdin);
+    // When
+    let result = run_init(temp.path())?;
 
-        if cont {
-            writer.write_fmt(format_args!("Y\n"))?;
-        } else {
-            writer.write_fmt(format_args!("N\n"))?;
-        }
-    }
+    // Then
+    assert_eq!(status_code(&result), 1);
+    assert!(stderr(&result).contains("line 2: invalid rule 're:(unclosed'"));
 
-    c.wait_with_output().map_err(anyhow::Error::new)
+    Ok(())
 }
 
-fn given_dir_without_index(base: &Path) -> Result<()> {
-    given_file_with_random_contents(base, "f1.txt", 4 * 1024)?;
-    given_file_with_contents(base, "a/f2a.txt", "f2")?;
-    given_file_with_contents(base, "a/f2b.txt", "f2")?;
-    given_file_with_random_contents(base, "a/b/f3.txt", 123)?;
-    given_file_with_random_contents(base, "c/f4.txt", 12345)?;
-    given_file_with_random_contents(base, "c/large.txt", 10 * 1024 * 1024)?;
+#[test]
+fn test_audit_copied() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    std::fs::create_dir(temp.path().join("d"))?;
+    std::fs::copy(temp.path().join("c/f4.txt"), temp.path().join("d/f4.txt"))?;
+
+    // When
+    let result = run_audit(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    let out = stdout(&result);
+    assert!(out.contains("[=] d/f4.txt (copy of c/f4.txt)"));
+    assert!(match_regex(&out, r"(?m)^Copied:\s+1$"));
+    assert!(match_regex(&out, r"(?m)^Copied \(bytes\):\s+12345$"));
+    assert!(match_regex(&out, r"(?m)^Unchanged:\s+6$"));
+    assert!(!out.contains("New:"));
+
     Ok(())
 }
 
-fn given_dir_with_index(base: &Path) -> Result<()> {
-    given_dir_without_index(base)?;
-    let result = run_init(base)?;
-    if status_code(&result) != 0 {
-        bail!("init failed");
-    }
+#[test]
+fn test_audit_moved_modified() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    let result = run_with_flag("init", "--chunks", temp.path())?;
+    assert_eq!(status_code(&result), 0);
+    assert!(temp.path().join(".auditr-chunks").is_dir());
+
+    std::fs::create_dir(temp.path().join("d"))?;
+    std::fs::rename(temp.path().join("c/large.txt"), temp.path().join("d/large.txt"))?;
+    let mut file = std::fs::OpenOptions::new().append(true).open(temp.path().join("d/large.txt"))?;
+    file.write_all(b"appended")?;
+
+    // When
+    let result = run_audit(temp.path())?;
+    let similar = auditr().
+        arg("audit").
+        arg("--similarity").
+        arg("90").
+        arg(temp.path().to_string_lossy().as_ref()).
+        output()?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+    assert!(stdout(&result).contains("[+] d/large.txt"));
+    assert!(stdout(&result).contains("[-] c/large.txt"));
+
+    assert_eq!(status_code(&similar), 2);
+    let out = stdout(&similar);
+    assert!(match_regex(&out, r"\[%\] d/large.txt \(from c/large.txt, (99|100)% similar\)"));
+    assert!(match_regex(&out, r"(?m)^Moved\+modified:\s+1$"));
+    assert!(!out.contains("New:"));
+    assert!(!out.contains("Removed:"));
+
     Ok(())
 }
 
-fn given_dir_with_modified_index(base: &Path, bitrot: bool) -> Result<()> {
-    given_dir_with_index(base)?;
+#[test]
+fn test_audit_moved_dir() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    std::fs::create_dir(temp.path().join("archive"))?;
+    std::fs::rename(temp.path().join("c"), temp.path().join("archive/c"))?;
 
-    given_file_with_random_contents(base, "a/new.txt", 10 * 1024)?; // New file
-    replace_file_with_contents(base, "f1.txt", "new contents", false)?; // Updated file
-    replace_file_with_contents(base, "a/f2b.txt", "f2", false)?; // Updated file, only mtime
-    std::fs::remove_dir_all(base.join("a/b"))?; // Removed file
-    std::fs::rename(base.join("c/large.txt"), base.join("a/large_new.txt"))?; // Moved file
+    // When
+    let result = run_audit(temp.path())?;
+    let verbose = run_with_flag("audit", "--verbose", temp.path())?;
 
-    if bitrot {
-        replace_file_with_contents(base, "a/f2a.txt", "new contents", true)?; // File with bitrot
-    }
+    // Then
+    assert_eq!(status_code(&result), 2);
+    let out = stdout(&result);
+    assert!(out.contains("[>] archive/c/ (directory moved from c/, 2 files)"));
+    assert!(!out.contains("[>] archive/c/f4.txt"));
+    assert!(match_regex(&out, r"(?m)^Moved:\s+2$"));
+
+    let out = stdout(&verbose);
+    assert!(out.contains("[>] archive/c/ (directory moved from c/, 2 files)"));
+    assert!(out.contains("[>] archive/c/f4.txt (from c/f4.txt)"));
+    assert!(out.contains("[>] archive/c/large.txt (from c/large.txt)"));
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_audit_summary() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    std::fs::remove_file(temp.path().join("a/b/f3.txt"))?;
+    given_file_with_contents(temp.path(), "d/new.txt", "new contents")?;
+    std::fs::rename(temp.path().join("c/large.txt"), temp.path().join("a/large.txt"))?;
+
+    // When
+    let result = auditr().
+        arg("audit").
+        arg("--summary").
+        arg("1").
+        arg("--sort").
+        arg("bytes").
+        arg(temp.path().to_string_lossy().as_ref()).
+        output()?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^c\s+0\s+0\s+0\s+1\s+-10485760\na\s+0\s+1\s+0\s+1\s+\+10485637\nd\s+1\s+0\s+0\s+0\s+\+12$"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_report() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let out = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    std::fs::remove_file(temp.path().join("a/b/f3.txt"))?;
+    let report = out.path().join("report.html");
+
+    // When
+    let result = auditr().
+        arg("audit").
+        arg("--report").
+        arg(report.to_string_lossy().as_ref()).
+        arg(temp.path().to_string_lossy().as_ref()).
+        output()?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    let html = std::fs::read_to_string(&report)?;
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("Audit failed - difference detected!"));
+    assert!(html.contains("<tr><th>Removed</th><td class=\"num\">1</td></tr>"));
+    assert!(html.contains("<li class=\"removed\">[-] f3.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_format_junit() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    std::fs::remove_file(temp.path().join("a/b/f3.txt"))?;
+
+    // When
+    let result = run_audit_format(temp.path(), "junit")?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    let out = stdout(&result);
+    assert!(out.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+    assert!(out.contains("<testsuites name=\"auditr\" tests=\"6\" failures=\"1\">"));
+    assert!(out.contains("name=\"a/b/f3.txt\">\n      <failure type=\"removed\" message=\"File was removed\"/>"));
+    assert!(out.contains("name=\"c/f4.txt\"/>"));
+    assert!(out.trim_end().ends_with("</testsuites>"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_format_sarif() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    std::fs::remove_file(temp.path().join("a/b/f3.txt"))?;
+
+    // When
+    let result = run_audit_format(temp.path(), "sarif")?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    let log: serde_json::Value = serde_json::from_str(&stdout(&result))?;
+    assert_eq!(log["version"], "2.1.0");
+    let results = log["runs"][0]["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["ruleId"], "removed");
+    assert_eq!(results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "a/b/f3.txt");
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_format_signed_index() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let key = tempdir()?;
+    let key_file = key.path().join("key");
+    given_file_with_contents(key.path(), "key", "secret")?;
+    given_dir_without_index(temp.path())?;
+    let result = run_with_key("init", temp.path(), &key_file)?;
+    assert_eq!(status_code(&result), 0);
+
+    // When
+    let junit = run_audit_format(temp.path(), "junit")?;
+    let sarif = run_audit_format(temp.path(), "sarif")?;
+
+    // Then
+    assert_eq!(status_code(&junit), 0);
+    let out = stdout(&junit);
+    assert!(out.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+    assert!(out.trim_end().ends_with("</testsuites>"));
+    assert!(!out.contains("Warning"));
+    assert!(stderr(&junit).contains("the index is signed, but no key was given"));
+
+    assert_eq!(status_code(&sarif), 0);
+    let log: serde_json::Value = serde_json::from_str(&stdout(&sarif))?;
+    assert_eq!(log["version"], "2.1.0");
+    assert!(log["runs"][0]["results"].as_array().unwrap().is_empty());
+    assert!(stderr(&sarif).contains("the index is signed, but no key was given"));
+
+    Ok(())
+}
+
+#[test]
+fn test_config() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let home = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    given_file_with_contents(home.path(), ".config/auditr/config.toml", "format = \"junit\"\nexclude = [\"a/*\"]\n")?;
+    given_file_with_contents(temp.path(), ".auditr.toml", "exclude = [\"c/*\"]\n")?;
+
+    let result = run_with_home(&["init"], temp.path(), home.path())?;
+    assert_eq!(status_code(&result), 0);
+
+    // When
+    let junit = run_with_home(&["audit"], temp.path(), home.path())?;
+    let text = run_with_home(&["audit", "--format", "text"], temp.path(), home.path())?;
+
+    // Then
+    assert_eq!(status_code(&junit), 0);
+    assert!(stdout(&junit).contains("<testsuites name=\"auditr\" tests=\"4\" failures=\"0\">"));
+    assert_eq!(status_code(&text), 0);
+    assert!(match_regex(&stdout(&text), r"(?m)^Total:\s+4$"));
+
+    Ok(())
+}
+
+#[test]
+fn test_config_flag_disabled_by_command_line() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    given_file_with_contents(temp.path(), "target/debug/out", "out")?;
+    given_file_with_contents(temp.path(), ".auditr.toml", "respect-gitignore = true\n")?;
+
+    // When
+    let configured = run_with_flag("ls-files", "--exclude=a/*", temp.path())?;
+    let disabled = run_with_flag("ls-files", "--no-respect-gitignore", temp.path())?;
+    let last_wins = auditr().
+        args(["ls-files", "--no-respect-gitignore", "--respect-gitignore"]).
+        arg(temp.path().to_string_lossy().as_ref()).
+        output()?;
+
+    // Then
+    assert_eq!(status_code(&configured), 0);
+    assert!(!stdout(&configured).contains("target/debug/out"));
+    assert_eq!(status_code(&disabled), 0);
+    assert!(stdout(&disabled).lines().any(|l| l == "target/debug/out"));
+    assert_eq!(status_code(&last_wins), 0);
+    assert!(!stdout(&last_wins).contains("target/debug/out"));
+
+    Ok(())
+}
+
+#[test]
+fn test_config_show() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let home = tempdir()?;
+    given_file_with_contents(home.path(), ".config/auditr/config.toml", "parity = 10\nformat = \"junit\"\n")?;
+    given_file_with_contents(temp.path(), ".auditr.toml", "parity = 20\n")?;
+
+    // When
+    let result = run_with_home(&["config", "show"], temp.path(), home.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    let root_config = temp.path().join(".auditr.toml");
+    let user_config = home.path().join(".config/auditr/config.toml");
+    assert!(out.contains(&format!("parity = 20  # {}", root_config.display())));
+    assert!(out.contains(&format!("format = \"junit\"  # {}", user_config.display())));
+    assert!(out.contains("normalization = \"nfc\"  # default"));
+    assert!(out.contains("# similarity is not set"));
+
+    given_file_with_contents(temp.path(), ".auditr.toml", "jobs = 4\n")?;
+    let result = run_with_home(&["config", "show"], temp.path(), home.path())?;
+    assert_eq!(status_code(&result), 1);
+    assert!(stderr(&result).contains("unknown field `jobs`"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_normalization_rename() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_file_with_contents(temp.path(), "cafe\u{301}.txt", "coffee")?;
+    given_dir_with_index(temp.path())?;
+    std::fs::rename(temp.path().join("cafe\u{301}.txt"), temp.path().join("caf\u{e9}.txt"))?;
+
+    // When
+    let result = run_audit(temp.path())?;
+    let result_none = run_with_flag("audit", "--normalization=none", temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    let out = stdout(&result);
+    assert!(out.contains("[~] caf\u{e9}.txt (renamed from NFD to NFC)"));
+    assert!(match_regex(&out, r"(?m)^Renamed \(NFC/NFD\):\s+1$"));
+    assert!(!out.contains("[>]"));
+
+    assert_eq!(status_code(&result_none), 2);
+    assert!(stdout(&result_none).contains("[>] caf\u{e9}.txt (from cafe\u{301}.txt)"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_normalization_collision() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    given_file_with_contents(temp.path(), "caf\u{e9}.txt", "nfc")?;
+    given_file_with_contents(temp.path(), "cafe\u{301}.txt", "nfd")?;
+
+    // When
+    let result = run_audit(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    assert!(stderr(&result).contains("Warning: 'caf\u{e9}.txt' exists in different normalization forms (NFD and NFC)!"));
+    assert!(match_regex(&stdout(&result), r"(?m)^New:\s+2$"));
+
+    Ok(())
+}
+
+#[test]
+fn test_dupes() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    given_file_with_contents(temp.path(), "c/copy.txt", "f2")?;
+    let result = run_audit_update(temp.path())?;
+    assert_eq!(status_code(&result), 2);
+
+    // When
+    let result = run_with_flag("dupes", "--json", temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let report: serde_json::Value = serde_json::from_str(&stdout(&result))?;
+    assert_eq!(report["wasted"], 4);
+    assert_eq!(report["files"], 3);
+    assert_eq!(report["sets"][0]["paths"], serde_json::json!(["a/f2a.txt", "a/f2b.txt", "c/copy.txt"]));
+
+    Ok(())
+}
+
+#[test]
+fn test_dupes_verify() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    replace_file_with_contents(temp.path(), "a/f2b.txt", "f3", true)?;
+
+    // When
+    let result = run_with_flag("dupes", "--verify", temp.path())?;
+    let result_index = run_with_flag("dupes", "--json", temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+    assert!(stdout(&result).contains("No duplicates found."));
+
+    assert_eq!(status_code(&result_index), 0);
+    assert!(stdout(&result_index).contains("a/f2b.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn test_dupes_verify_missing() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    std::fs::remove_file(temp.path().join("a/f2b.txt"))?;
+
+    // When
+    let result = run_with_flag("dupes", "--verify", temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(out.contains("[-] a/f2b.txt (missing)"));
+    assert!(match_regex(&out, r"(?m)^Missing:\s+1$"));
+    assert!(out.contains("No duplicates found."));
+
+    Ok(())
+}
+
+#[test]
+fn test_dupes_key() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let key = tempdir()?;
+    let key_file = key.path().join("key");
+    given_file_with_contents(key.path(), "key", "secret")?;
+    given_dir_without_index(temp.path())?;
+    let result = run_with_key("init", temp.path(), &key_file)?;
+    assert_eq!(status_code(&result), 0);
+    std::fs::write(temp.path().join(HASH_INDEX_FILENAME), "")?;
+    std::fs::write(temp.path().join(META_INDEX_FILENAME), "")?;
+
+    // When
+    let result = run_with_key("dupes", temp.path(), &key_file)?;
+
+    // Then
+    assert_eq!(status_code(&result), 1);
+    assert!(stderr(&result).contains("Index signature is missing or invalid"));
+
+    Ok(())
+}
+
+#[test]
+fn test_dupes_repair_config() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let home = tempdir()?;
+    let key_file = home.path().join(".config/auditr/key");
+    given_file_with_contents(home.path(), ".config/auditr/key", "secret")?;
+    given_file_with_contents(home.path(), ".config/auditr/config.toml", "key = \"key\"\n")?;
+    given_dir_with_index(temp.path())?;
+    given_file_with_contents(temp.path(), "c/copy.txt", "f2")?;
+    let result = run_audit_update(temp.path())?;
+    assert_eq!(status_code(&result), 2);
+    let result = run_with_key("sign", temp.path(), &key_file)?;
+    assert_eq!(status_code(&result), 0);
+    given_file_with_contents(temp.path(), ".auditr.toml", "exclude = [\"c/*\"]\n")?;
+
+    // When
+    let dupes = run_with_home(&["dupes", "--json"], temp.path(), home.path())?;
+    let repair = run_with_home(&["repair", "--parity"], temp.path(), home.path())?;
+
+    // Then
+    assert_eq!(status_code(&dupes), 0);
+    assert!(!stderr(&dupes).contains("no key was given"));
+    let report: serde_json::Value = serde_json::from_str(&stdout(&dupes))?;
+    assert_eq!(report["sets"][0]["paths"], serde_json::json!(["a/f2a.txt", "a/f2b.txt"]));
+
+    assert_eq!(status_code(&repair), 0);
+    assert!(!stderr(&repair).contains("no key was given"));
+    assert!(stdout(&repair).contains("Nothing to repair."));
+
+    // A key in the configuration of the audited directory is rejected
+    given_file_with_contents(temp.path(), ".auditr.toml", "key = \"/tmp/key\"\n")?;
+    let result = run_with_home(&["dupes"], temp.path(), home.path())?;
+    assert_eq!(status_code(&result), 1);
+    assert!(stderr(&result).contains("key can only be set in the user configuration"));
+
+    Ok(())
+}
+
+#[test]
+fn test_check_ignore() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    given_file_with_contents(temp.path(), GLOB_FILTER_FILENAME, "# comment\n*.log\nc\n!a/f2a.txt\n")?;
+
+    // When
+    let result = run_check_ignore(temp.path(), &["x.log", "a/f2a.txt", "c/f4.txt", "f1.txt"])?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(out.contains(&format!("x.log: excluded by {}:2 '*.log'", GLOB_FILTER_FILENAME)));
+    assert!(out.contains(&format!("a/f2a.txt: included by {}:4 '!a/f2a.txt'", GLOB_FILTER_FILENAME)));
+    assert!(out.contains(&format!("c/f4.txt: excluded by {}:3 'c' (via c)", GLOB_FILTER_FILENAME)));
+    assert!(out.contains("f1.txt: included (default)"));
+
+    Ok(())
+}
+
+#[test]
+fn test_ls_files() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    given_file_with_contents(temp.path(), GLOB_FILTER_FILENAME, "c\n")?;
+
+    // When
+    let result = run_with_flag("ls-files", "--exclude=a/b/*", temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+    assert_eq!(stdout(&result).lines().collect::<Vec<&str>>(), vec!["a/f2a.txt", "a/f2b.txt", "f1.txt"]);
+    assert!(!temp.path().join(HASH_INDEX_FILENAME).exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_filter_update() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let path = temp.path();
+    // let path = Path::new("/tmp/test");
+    given_file_with_contents(path, GLOB_FILTER_FILENAME, indoc!("
+        !a/b*
+        a/**
+    "))?;
+    given_dir_with_modified_index(path, true)?;
+
+    // When
+    let result = run_update(temp.path(), true)?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Updated:\s+1$"));
+    assert!(match_regex(&out, r"(?m)^Removed:\s+2$"));
+    assert!(match_regex(&out, r"(?m)^Unchanged:\s+1$"));
+    assert!(match_regex(&out, r"(?m)^Total:\s+2$"));
+
+    assert!(out.contains("[*] f1.txt"));
+    assert!(out.contains("[-] a/b/f3.txt"));
+    assert!(out.contains("[-] c/large.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_reports_files_skipped_by_meta_rules() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    given_file_with_contents(temp.path(), GLOB_FILTER_FILENAME, "mtime<1h\n")?;
+
+    // When
+    let result = run_audit_update(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 4);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Skipped:\s+6$"));
+    assert!(!match_regex(&out, r"(?m)^Removed:"));
+    assert!(out.contains("[?] a/f2a.txt (skipped by a metadata rule, not verified)"));
+    assert!(out.contains("Audit incomplete - 6 files were skipped and not verified!"));
+
+    let hash_index = std::fs::read_to_string(temp.path().join(HASH_INDEX_FILENAME))?;
+    assert_eq!(hash_index.lines().count(), 6);
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_update_keeps_files_skipped_by_meta_rules() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    given_file_with_contents(temp.path(), GLOB_FILTER_FILENAME, "size>1M\n")?;
+    given_file_with_contents(temp.path(), "new.txt", "new")?;
+
+    // When
+    let result = run_audit_update(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Skipped:\s+1$"));
+    assert!(out.contains("[?] c/large.txt (skipped by a metadata rule, not verified)"));
+
+    let hash_index = std::fs::read_to_string(temp.path().join(HASH_INDEX_FILENAME))?;
+    assert_eq!(hash_index.lines().count(), 7);
+    assert!(hash_index.contains("  c/large.txt\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_repair() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let mirror = tempdir()?;
+    given_dir_with_modified_index(temp.path(), true)?;
+    given_dir_without_index(mirror.path())?;
+
+    // When
+    let result = run_repair(temp.path(), mirror.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Repaired:\s+1$"));
+    assert!(out.contains("[R] a/f2a.txt (from a/f2a.txt)"));
+
+    let result = run_audit(temp.path())?;
+    let out = stdout(&result);
+    assert_eq!(status_code(&result), 2);
+    assert!(!match_regex(&out, r"(?m)^Updated \(bitrot\):"));
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_from_other_path() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let mirror = tempdir()?;
+    given_dir_with_modified_index(temp.path(), true)?;
+    given_file_with_contents(mirror.path(), "elsewhere/copy.txt", "f2")?;
+
+    // When
+    let result = run_repair(temp.path(), mirror.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+    assert!(stdout(&result).contains("[R] a/f2a.txt (from elsewhere/copy.txt)"));
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_unrecoverable() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let mirror = tempdir()?;
+    given_dir_with_modified_index(temp.path(), true)?;
+    given_file_with_contents(mirror.path(), "a/f2a.txt", "also rotten")?;
+
+    // When
+    let result = run_repair(temp.path(), mirror.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 3);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Unrecoverable:\s+1$"));
+    assert!(out.contains("[!] a/f2a.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_bitrot_blocks() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    let result = run_init_blocks(temp.path())?;
+    assert_eq!(status_code(&result), 0);
+    replace_file_with_contents(temp.path(), "a/f2a.txt", "f3", true)?;
+
+    // When
+    let result = run_audit(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 3);
+    assert!(stdout(&result).contains("Corrupted bytes of a/f2a.txt: 0-1"));
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_blocks() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let mirror = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    given_dir_without_index(mirror.path())?;
+    let result = run_init_blocks(temp.path())?;
+    assert_eq!(status_code(&result), 0);
+    replace_file_with_contents(temp.path(), "a/f2a.txt", "f3", true)?;
+
+    // When
+    let result = run_repair(temp.path(), mirror.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let result = run_audit(temp.path())?;
+    assert_eq!(status_code(&result), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_parity() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    let result = run_init_parity(temp.path(), 20)?;
+    assert_eq!(status_code(&result), 0);
+    replace_file_with_contents(temp.path(), "a/f2a.txt", "f3", true)?;
+
+    // When
+    let result = run_repair_parity(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+    assert!(stdout(&result).contains("[R] a/f2a.txt (from parity data)"));
+
+    let result = run_audit(temp.path())?;
+    assert_eq!(status_code(&result), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_continues_after_error() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_without_index(temp.path())?;
+    let result = run_init_parity(temp.path(), 20)?;
+    assert_eq!(status_code(&result), 0);
+
+    let mut contents = std::fs::read_to_string(temp.path().join("f1.txt"))?;
+    contents.replace_range(100..101, if &contents[100..101] == "x" { "y" } else { "x" });
+    replace_file_with_contents(temp.path(), "f1.txt", &contents, true)?;
+    replace_file_with_contents(temp.path(), "a/f2a.txt", "f3", true)?;
+
+    // Parity data of "f2"
+    let parity_file = temp.path().join(".auditr-parity/e4ab4e3b1493d5a997b4e51cdefbaa10570ef3ea9432bd72e7b6a89654ceb7f6");
+    std::fs::write(parity_file, "not a parity file, but long enough for a header")?;
+
+    // When
+    let result = run_repair_parity(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 3);
+
+    let out = stdout(&result);
+    assert!(out.contains("[R] f1.txt (from parity data)"));
+    assert!(out.contains("[!] a/f2a.txt (invalid parity file)"));
+    assert!(match_regex(&out, r"(?m)^Repaired:\s+1$"));
+    assert!(match_regex(&out, r"(?m)^Unrecoverable:\s+1$"));
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_respects_exclude() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    let mirror = tempdir()?;
+    given_dir_with_index(temp.path())?;
+    given_dir_without_index(mirror.path())?;
+    replace_file_with_contents(temp.path(), "a/f2a.txt", "f3", true)?;
+
+    // When
+    let result = auditr().
+        arg("repair").
+        arg("--exclude").
+        arg("a/f2a.txt").
+        arg("--from").
+        arg(mirror.path().to_string_lossy().as_ref()).
+        arg(temp.path().to_string_lossy().as_ref()).
+        output()?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+    assert!(stdout(&result).contains("Nothing to repair."));
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_identical() -> Result<()> {
+    // Given
+    let primary = tempdir()?;
+    let backup = tempdir()?;
+    given_dir_with_index(primary.path())?;
+    given_dir_without_index(backup.path())?;
+
+    // When
+    let result = run_compare(primary.path(), backup.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Identical:\s+6$"));
+    assert!(out.contains("backup is a faithful replica"));
+
+    let primary_hash = out.lines().find_map(|l| l.strip_prefix("Primary root hash: "));
+    let backup_hash = out.lines().find_map(|l| l.strip_prefix("Backup root hash: "));
+    assert!(primary_hash.is_some());
+    assert_eq!(primary_hash, backup_hash);
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_tampered_dir_index() -> Result<()> {
+    // Given
+    let primary = tempdir()?;
+    let backup = tempdir()?;
+    given_dir_with_index(primary.path())?;
+    given_dir_with_index(backup.path())?;
+    let dir_index = std::fs::read_to_string(backup.path().join(DIR_INDEX_FILENAME))?;
+    std::fs::write(backup.path().join(DIR_INDEX_FILENAME), dir_index.replacen("  .\n", "0  .\n", 1))?;
+
+    // When
+    let result = run_compare(primary.path(), backup.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 1);
+    assert!(stderr(&result).contains("do not match the index"));
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_key() -> Result<()> {
+    // Given
+    let primary = tempdir()?;
+    let backup = tempdir()?;
+    let key = tempdir()?;
+    let key_file = key.path().join("key");
+    given_file_with_contents(key.path(), "key", "secret")?;
+    given_dir_without_index(primary.path())?;
+    given_dir_without_index(backup.path())?;
+    assert_eq!(status_code(&run_with_key("init", primary.path(), &key_file)?), 0);
+    assert_eq!(status_code(&run_with_key("init", backup.path(), &key_file)?), 0);
+
+    // Tampered backup index matching the primary one
+    std::fs::write(backup.path().join("f1.txt"), "tampered")?;
+    std::fs::write(backup.path().join(HASH_INDEX_FILENAME), std::fs::read(primary.path().join(HASH_INDEX_FILENAME))?)?;
+
+    // When
+    let result = auditr().
+        args(["compare", &primary.path().to_string_lossy(), &backup.path().to_string_lossy()]).
+        args(["--key", &key_file.to_string_lossy()]).
+        output()?;
+
+    // Then
+    assert_eq!(status_code(&result), 1);
+    assert!(stderr(&result).contains("Index signature is missing or invalid"));
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_backup_key() -> Result<()> {
+    // Given
+    let primary = tempdir()?;
+    let backup = tempdir()?;
+    let keys = tempdir()?;
+    given_file_with_contents(keys.path(), "primary", "primary secret")?;
+    given_file_with_contents(keys.path(), "backup", "backup secret")?;
+    given_dir_without_index(primary.path())?;
+    given_dir_without_index(backup.path())?;
+    assert_eq!(status_code(&run_with_key("init", primary.path(), &keys.path().join("primary"))?), 0);
+    assert_eq!(status_code(&run_with_key("init", backup.path(), &keys.path().join("backup"))?), 0);
+
+    // When
+    let result = auditr().
+        args(["compare", &primary.path().to_string_lossy(), &backup.path().to_string_lossy()]).
+        args(["--key", &keys.path().join("primary").to_string_lossy()]).
+        args(["--backup-key", &keys.path().join("backup").to_string_lossy()]).
+        output()?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+    assert!(stdout(&result).contains("backup is a faithful replica"));
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_backup_config() -> Result<()> {
+    // Given
+    let primary = tempdir()?;
+    let backup = tempdir()?;
+    given_dir_without_index(primary.path())?;
+    given_dir_without_index(backup.path())?;
+    given_file_with_contents(backup.path(), "x.tmp", "temporary")?;
+    given_file_with_contents(backup.path(), ".auditr.toml", "exclude = [\"*.tmp\"]\n")?;
+
+    // When
+    let result = run_compare(primary.path(), backup.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+    assert!(stderr(&result).contains("No index found in directory"));
+    assert!(match_regex(&stdout(&result), r"(?m)^Identical:\s+6$"));
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_differences() -> Result<()> {
+    // Given
+    let primary = tempdir()?;
+    let backup = tempdir()?;
+    given_dir_with_index(primary.path())?;
+    given_dir_with_modified_index(backup.path(), false)?;
+    std::fs::remove_file(backup.path().join(HASH_INDEX_FILENAME))?;
+    std::fs::remove_file(backup.path().join(META_INDEX_FILENAME))?;
+
+    // When
+    let result = run_compare(primary.path(), backup.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+
+    let out = stdout(&result);
+    assert!(match_regex(&out, r"(?m)^Missing in backup:\s+1$"));
+    assert!(match_regex(&out, r"(?m)^Content differs:\s+1$"));
+    assert!(match_regex(&out, r"(?m)^Only in backup:\s+1$"));
+    assert!(match_regex(&out, r"(?m)^Moved:\s+1$"));
+    assert!(match_regex(&out, r"(?m)^Identical:\s+3$"));
+
+    assert!(out.contains("[-] a/b/f3.txt"));
+    assert!(out.contains("[*] f1.txt"));
+    assert!(out.contains("[+] a/new.txt"));
+    assert!(out.contains("[>] a/large_new.txt (from c/large.txt)"));
+
+    Ok(())
+}
+
+fn run_init(base: &Path) -> io::Result<Output> {
+    let path = base.to_string_lossy();
+    auditr().
+        arg("init").
+        arg(path.as_ref()).
+        output()
+}
+
+fn run_init_parity(base: &Path, percent: u32) -> io::Result<Output> {
+    let path = base.to_string_lossy();
+    auditr().
+        arg("init").
+        arg("--parity").
+        arg(percent.to_string()).
+        arg(path.as_ref()).
+        output()
+}
+
+fn run_init_blocks(base: &Path) -> io::Result<Output> {
+    let path = base.to_string_lossy();
+    auditr().
+        arg("init").
+        arg("--blocks").
+        arg(path.as_ref()).
+        output()
+}
+
+fn run_audit(base: &Path) -> io::Result<Output> {
+    let path = base.to_string_lossy();
+    auditr().
+        arg("audit").
+        arg(path.as_ref()).
+        output()
+}
+
+fn run_audit_format(base: &Path, format: &str) -> io::Result<Output> {
+    let path = base.to_string_lossy();
+    auditr().
+        arg("audit").
+        arg("--format").
+        arg(format).
+        arg(path.as_ref()).
+        output()
+}
+
+fn run_audit_update(base: &Path) -> io::Result<Output> {
+    let path = base.to_string_lossy();
+    auditr().
+        arg("audit").
+        arg("--update").
+        arg(path.as_ref()).
+        output()
+}
+
+fn run_repair(base: &Path, mirror: &Path) -> io::Result<Output> {
+    auditr().
+        arg("repair").
+        arg(base.to_string_lossy().as_ref()).
+        arg("--from").
+        arg(mirror.to_string_lossy().as_ref()).
+        output()
+}
+
+fn run_repair_parity(base: &Path) -> io::Result<Output> {
+    auditr().
+        arg("repair").
+        arg("--parity").
+        arg(base.to_string_lossy().as_ref()).
+        output()
+}
+
+fn run_compare(primary: &Path, backup: &Path) -> io::Result<Output> {
+    auditr().
+        arg("compare").
+        arg(primary.to_string_lossy().as_ref()).
+        arg(backup.to_string_lossy().as_ref()).
+        output()
+}
+
+fn run_journal_verify(base: &Path) -> io::Result<Output> {
+    auditr().
+        arg("journal").
+        arg("verify").
+        arg(base.to_string_lossy().as_ref()).
+        output()
+}
+
+fn run_check_ignore(base: &Path, paths: &[&str]) -> io::Result<Output> {
+    auditr().
+        arg("check-ignore").
+        arg(base.to_string_lossy().as_ref()).
+        args(paths).
+        output()
+}
+
+fn run_with_home(args: &[&str], base: &Path, home: &Path) -> io::Result<Output> {
+    auditr().
+        args(args).
+        arg(base.to_string_lossy().as_ref()).
+        env("HOME", home).
+        output()
+}
+
+fn run_with_flag(command: &str, flag: &str, base: &Path) -> io::Result<Output> {
+    auditr().
+        arg(command).
+        arg(flag).
+        arg(base.to_string_lossy().as_ref()).
+        output()
+}
+
+fn run_with_key(command: &str, base: &Path, key_file: &Path) -> io::Result<Output> {
+    auditr().
+        arg(command).
+        arg("--key").
+        arg(key_file.to_string_lossy().as_ref()).
+        arg(base.to_string_lossy().as_ref()).
+        output()
+}
+
+fn run_update(base: &Path, cont: bool) -> Result<Output> {
+    let path = base.to_string_lossy();
+    let mut c = auditr().
+        arg("update").
+        arg(path.as_ref()).
+        stdin(Stdio::piped()).
+        stdout(Stdio::piped()).
+        stderr(Stdio::piped()).
+        spawn()?;
+
+    if let Some(ref mut stdin) = c.stdin {
+        let mut writer = BufWriter::new(stdin);
+
+        if cont {
+            writer.write_fmt(format_args!("Y\n"))?;
+        } else {
+            writer.write_fmt(format_args!("N\n"))?;
+        }
+    }
+
+    c.wait_with_output().map_err(anyhow::Error::new)
+}
+
+#[test]
+fn test_journal_verify() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_modified_index(temp.path(), false)?;
+    run_audit(temp.path())?;
+
+    // When
+    let result = run_journal_verify(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 0);
+
+    let out = stdout(&result);
+    assert!(out.contains("Journal verified - 2 entries."));
+    assert!(match_regex(&out, r"(?m)^Head hash: [0-9a-f]{64}$"));
+
+    let journal = std::fs::read_to_string(temp.path().join(JOURNAL_FILENAME))?;
+    assert!(journal.starts_with("seq=1 "));
+    assert!(journal.contains(" command=audit exit=2 "));
+    assert!(journal.contains(" added=1 copied=0 removed=1 updated=2 bitrot=0 moved=1 moved_modified=0 renamed=0 unchanged=2 total=6 "));
+
+    Ok(())
+}
+
+#[test]
+fn test_journal_verify_modified() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_modified_index(temp.path(), false)?;
+    run_audit(temp.path())?;
+
+    let journal = std::fs::read_to_string(temp.path().join(JOURNAL_FILENAME))?;
+    std::fs::write(temp.path().join(JOURNAL_FILENAME), journal.replace("exit=2", "exit=0"))?;
+
+    // When
+    let result = run_journal_verify(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+    assert!(stdout(&result).contains("journal entry 2 has been modified"));
+
+    Ok(())
+}
+
+#[test]
+fn test_journal_unwritable() -> Result<()> {
+    // Given
+    let temp = tempdir()?;
+    given_dir_with_modified_index(temp.path(), false)?;
+    std::fs::remove_file(temp.path().join(JOURNAL_FILENAME))?;
+    std::fs::create_dir(temp.path().join(JOURNAL_FILENAME))?;
+
+    // When
+    let result = run_audit(temp.path())?;
+
+    // Then
+    assert_eq!(status_code(&result), 2);
+    assert!(stderr(&result).contains("Warning: failed to append to the journal"));
+
+    Ok(())
+}
+
+fn given_dir_without_index(base: &Path) -> Result<()> {
+    given_file_with_random_contents(base, "f1.txt", 4 * 1024)?;
+    given_file_with_contents(base, "a/f2a.txt", "f2")?;
+    given_file_with_contents(base, "a/f2b.txt", "f2")?;
+    given_file_with_random_contents(base, "a/b/f3.txt", 123)?;
+    given_file_with_random_contents(base, "c/f4.txt", 12345)?;
+    given_file_with_random_contents(base, "c/large.txt", 10 * 1024 * 1024)?;
+    Ok(())
+}
+
+fn given_dir_with_index(base: &Path) -> Result<()> {
+    given_dir_without_index(base)?;
+    let result = run_init(base)?;
+    if status_code(&result) != 0 {
+        bail!("init failed");
+    }
+    Ok(())
+}
+
+fn given_dir_with_modified_index(base: &Path, bitrot: bool) -> Result<()> {
+    given_dir_with_index(base)?;
+
+    given_file_with_random_contents(base, "a/new.txt", 10 * 1024)?; // New file
+    replace_file_with_contents(base, "f1.txt", "new contents", false)?; // Updated file
+    replace_file_with_contents(base, "a/f2b.txt", "f2", false)?; // Updated file, only mtime
+    std::fs::remove_dir_all(base.join("a/b"))?; // Removed file
+    std::fs::rename(base.join("c/large.txt"), base.join("a/large_new.txt"))?; // Moved file
+
+    if bitrot {
+        replace_file_with_contents(base, "a/f2a.txt", "new contents", true)?; // File with bitrot
+    }
+
+    Ok(())
+}
+
+/// Returns a command running auditr independently of the configuration of the current user.
+fn auditr() -> Command {
+    let mut c = Command::new(BINARY_PATH);
+    c.env("HOME", MISSING_HOME).env_remove("XDG_CONFIG_HOME");
+    c
+}